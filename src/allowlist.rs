@@ -0,0 +1,56 @@
+/// Is the sender's address permitted to publish?
+/// Each entry is either a full address (`alice@example.org`) or a domain (`example.org` or `@example.org`).
+/// An empty allowlist permits everyone.
+pub fn permits(allowed: &[String], address: &str) -> bool {
+    allowed.is_empty() || matches(allowed, address)
+}
+
+/// Does the address match any of the entries, each either an address or a domain?
+pub fn matches(entries: &[String], address: &str) -> bool {
+    let address = address.trim().to_lowercase();
+    let domain = address.rsplit_once('@').map(|(_, domain)| domain);
+
+    entries.iter().any(|entry| {
+        let entry = entry.trim().to_lowercase();
+        if entry.contains('@') && !entry.starts_with('@') {
+            entry == address
+        } else {
+            domain == Some(entry.trim_start_matches('@'))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_empty_allowlist_permits_all() {
+        assert!(permits(&[], "anyone@example.com"));
+    }
+
+    #[test]
+    fn test_empty_list_matches_none() {
+        assert!(!matches(&[], "anyone@example.com"));
+    }
+
+    #[test]
+    fn test_exact_address() {
+        let list = allowed(&["alice@example.org"]);
+        assert!(permits(&list, "Alice@Example.org"));
+        assert!(!permits(&list, "bob@example.org"));
+    }
+
+    #[test]
+    fn test_domain() {
+        let list = allowed(&["example.org", "@example.net"]);
+        assert!(permits(&list, "bob@example.org"));
+        assert!(permits(&list, "carol@example.net"));
+        assert!(!permits(&list, "mallory@example.org.evil.com"));
+        assert!(!permits(&list, "example.org"));
+    }
+}
@@ -0,0 +1,198 @@
+use mailparse::{MailHeaderMap, ParsedMail};
+
+use super::dkim;
+use super::mishaps::Mishap;
+use super::settings::{AuthMechanism, Settings};
+
+/// Check the message was authenticated according to the configured policy.
+///
+/// Only the topmost `Authentication-Results` header from the trusted authserv-id is considered:
+/// the receiving server adds its header above the others, and any below it may come from the sender.
+pub fn check(settings: &Settings, mail: &ParsedMail, from_address: &str) -> Result<(), Mishap> {
+    // RFC 5322 allows a single From; with more, the one checked might not be the one shown
+    if mail.headers.get_all_headers("From").len() > 1 {
+        return Err(Mishap::EmailField("more than one From header".to_string()));
+    }
+
+    if let Some(authserv_id) = &settings.trusted_authserv_id {
+        let results: Vec<MethodResult> = mail
+            .headers
+            .get_all_values("Authentication-Results")
+            .iter()
+            .filter_map(|header| parse(header))
+            .find(|(id, _)| id.eq_ignore_ascii_case(authserv_id))
+            .map(|(_, results)| results)
+            .unwrap_or_default();
+
+        for mechanism in settings.require_auth.iter() {
+            let name = mechanism.as_str();
+            let outcomes: Vec<&MethodResult> =
+                results.iter().filter(|r| r.method == name).collect();
+
+            if !outcomes.iter().any(|r| r.result == "pass") {
+                let result = outcomes
+                    .first()
+                    .map(|r| r.result.clone())
+                    .unwrap_or_else(|| String::from("missing"));
+                return Err(Mishap::AuthenticationFailed(name.to_string(), result));
+            }
+        }
+    }
+
+    if let Some(key_file) = &settings.dkim_keys {
+        let keys = dkim::KeyStore::load(key_file)?;
+        let from_domain = from_address
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default();
+
+        let verdicts = dkim::verify(mail.raw_bytes, &keys);
+        let aligned = |domain: &String| {
+            from_domain == *domain || from_domain.ends_with(&format!(".{}", domain))
+        };
+
+        if !verdicts
+            .iter()
+            .any(|v| matches!(v, dkim::Verdict::Pass(domain) if aligned(domain)))
+        {
+            let reason = verdicts
+                .first()
+                .map(|v| match v {
+                    dkim::Verdict::Fail(reason) => reason.clone(),
+                    dkim::Verdict::Pass(domain) => format!("signed by {}", domain),
+                })
+                .unwrap_or_else(|| String::from("no signature"));
+            return Err(Mishap::AuthenticationFailed(
+                AuthMechanism::Dkim.as_str().to_string(),
+                reason,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `check` proves who sent a message, rather than taking the `From` address on trust:
+/// DKIM signatures are checked against the From domain, or the mail server's DMARC result must pass.
+pub fn verifies_sender(settings: &Settings) -> bool {
+    settings.dkim_keys.is_some()
+        || (settings.trusted_authserv_id.is_some()
+            && settings.require_auth.contains(&AuthMechanism::Dmarc))
+}
+
+/// A single `method=result` entry from an `Authentication-Results` header (RFC 8601).
+#[derive(Debug, PartialEq, Eq)]
+struct MethodResult {
+    method: String,
+    result: String,
+}
+
+/// Parse an `Authentication-Results` header value into the authserv-id and its results.
+fn parse(header: &str) -> Option<(String, Vec<MethodResult>)> {
+    let header = without_comments(header);
+    let mut sections = header.split(';');
+
+    let authserv_id = sections.next()?.split_whitespace().next()?.to_string();
+
+    let results = sections
+        .filter_map(|section| section.split_whitespace().next())
+        .filter_map(|method_result| method_result.split_once('='))
+        .map(|(method, result)| MethodResult {
+            method: method.split('/').next().unwrap_or(method).to_lowercase(),
+            result: result.to_lowercase(),
+        })
+        .collect();
+
+    Some((authserv_id, results))
+}
+
+/// Remove (possibly nested) parenthesised comments.
+fn without_comments(header: &str) -> String {
+    let mut depth = 0;
+    let mut text = String::new();
+    for c in header.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(method: &str, result: &str) -> MethodResult {
+        MethodResult {
+            method: method.to_string(),
+            result: result.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_gmail_style() {
+        let header = "mx.google.com;
+       dkim=pass header.i=@example.org header.s=20230601 header.b=Ab1/Cd2;
+       spf=pass (google.com: domain of alice@example.org designates 1.2.3.4 as permitted sender) smtp.mailfrom=alice@example.org;
+       dmarc=pass (p=NONE sp=NONE dis=NONE) header.from=example.org";
+
+        let (id, results) = parse(header).unwrap();
+        assert_eq!("mx.google.com", id);
+        assert_eq!(
+            vec![
+                result("dkim", "pass"),
+                result("spf", "pass"),
+                result("dmarc", "pass")
+            ],
+            results
+        );
+    }
+
+    #[test]
+    fn test_parse_with_version_and_failures() {
+        let header =
+            "example.net 1; dkim/1=fail (bad signature) header.d=example.org; spf=softfail";
+        let (id, results) = parse(header).unwrap();
+        assert_eq!("example.net", id);
+        assert_eq!(
+            vec![result("dkim", "fail"), result("spf", "softfail")],
+            results
+        );
+    }
+
+    fn mail(headers: &str) -> Vec<u8> {
+        format!("{}Subject: Hi\r\n\r\nHello\r\n", headers).into_bytes()
+    }
+
+    #[test]
+    fn test_forged_result_below_the_servers_ignored() {
+        let settings = Settings::for_test(&["--trusted-authserv-id=mx.example.net"]);
+        let raw = mail("Authentication-Results: mx.example.net; dmarc=fail header.from=example.org\r\nAuthentication-Results: mx.example.net; dmarc=pass header.from=example.org\r\nFrom: alice@example.org\r\n");
+        let parsed = mailparse::parse_mail(&raw).unwrap();
+        assert!(matches!(
+            check(&settings, &parsed, "alice@example.org"),
+            Err(Mishap::AuthenticationFailed(method, result)) if method == "dmarc" && result == "fail"
+        ));
+
+        let raw = mail("Authentication-Results: mx.example.net; dmarc=pass header.from=example.org\r\nFrom: alice@example.org\r\n");
+        let parsed = mailparse::parse_mail(&raw).unwrap();
+        assert!(check(&settings, &parsed, "alice@example.org").is_ok());
+    }
+
+    #[test]
+    fn test_two_from_headers_refused() {
+        let raw = mail("From: alice@example.org\r\nFrom: mallory@example.org\r\n");
+        let parsed = mailparse::parse_mail(&raw).unwrap();
+        assert!(check(&Settings::for_test(&[]), &parsed, "alice@example.org").is_err());
+    }
+
+    #[test]
+    fn test_parse_none() {
+        let (id, results) = parse("example.net; none").unwrap();
+        assert_eq!("example.net", id);
+        assert!(results.is_empty());
+    }
+}
@@ -1,10 +1,15 @@
+use super::frontmatter;
 use super::mishaps::Mishap;
+use super::settings::{OutputProfile, Settings};
 use chrono::{DateTime, Utc};
-use std::fs::File;
-use std::io::Write;
+use minijinja::{context, AutoEscape, Environment, ErrorKind, State, Value as JinjaValue};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use serde_yaml_ng::Value;
+use std::fs;
 use std::path::PathBuf;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct PostInfo {
     pub title: String,
     pub author: String,
@@ -14,20 +19,29 @@ pub struct PostInfo {
     pub attachments: Vec<Image>,
     pub relative_path: String,
     pub filename: PathBuf,
+    /// Other front matter, such as tags or a layout
+    pub front_matter: Vec<(String, Value)>,
+    pub message_id: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Image {
     pub file: PathBuf,
+    /// Where the file goes, relative to the root of the site
     pub relative_path: String,
+    /// How the post links to the image
+    pub url: String,
     pub mimetype: String,
+    /// The Content-ID (without angle brackets) the email body can refer to as `cid:...`
+    pub content_id: Option<String>,
     pub thumbnail: Thumbnail,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Thumbnail {
     pub file: PathBuf,
     pub relative_path: String,
+    pub url: String,
     pub width: u16,
     pub height: u16,
 }
@@ -43,6 +57,8 @@ impl PostInfo {
         attachments: Vec<Image>,
         relative_path: String,
         filename: PathBuf,
+        front_matter: Vec<(String, Value)>,
+        message_id: Option<String>,
     ) -> PostInfo {
         PostInfo {
             title: title.trim().to_owned(),
@@ -53,80 +69,361 @@ impl PostInfo {
             attachments,
             relative_path,
             filename,
+            front_matter,
+            message_id,
         }
     }
 }
 
-const JEKYL_SITE_URL: &str = "{{ site.url }}";
+/// The built-in post and image templates for each output profile.
+fn builtin_templates(profile: OutputProfile) -> (&'static str, &'static str) {
+    match profile {
+        OutputProfile::Jekyll => (
+            include_str!("templates/jekyll/post.md"),
+            include_str!("templates/jekyll/image.html"),
+        ),
+        OutputProfile::Hugo => (
+            include_str!("templates/hugo/post.md"),
+            include_str!("templates/hugo/image.html"),
+        ),
+        OutputProfile::Zola => (
+            include_str!("templates/zola/post.md"),
+            include_str!("templates/zola/image.html"),
+        ),
+        OutputProfile::Eleventy => (
+            include_str!("templates/eleventy/post.md"),
+            include_str!("templates/eleventy/image.html"),
+        ),
+    }
+}
 
-pub fn write(post: PostInfo) -> Result<PostInfo, Mishap> {
-    let markdown = File::create(&post.filename)?;
-    write!(&markdown, "{}", post_meta(&post))?;
-    write!(&markdown, "\n\n")?;
+pub fn write(settings: &Settings, post: PostInfo) -> Result<PostInfo, Mishap> {
+    let templates = templates(settings)?;
+    let markdown = render(&templates, settings.output_profile, &post)?;
+    fs::write(&post.filename, markdown)?;
+    Ok(post)
+}
 
-    match &post.content {
-        Some(text) => write!(&markdown, "{}\n\n", text)?,
-        None => {}
+/// The post and image templates: the user's own, or the built-in ones for the output profile.
+/// The post template sees the `post`, its `front_matter` as arranged for the output profile,
+/// its `content` (with inline images in place) and the `gallery` of other images.
+/// It can render an image with `image(...)`, and front matter with the `yaml` or `toml` filters.
+pub fn templates(settings: &Settings) -> Result<Environment<'static>, Mishap> {
+    let load = |file: &Option<PathBuf>, default: &str| match file {
+        Some(file) => fs::read_to_string(file),
+        None => Ok(default.to_string()),
     };
 
-    for image in post.attachments.iter() {
-        write!(&markdown, "{}", image_to_markdown(image))?;
-        write!(&markdown, "\n\n")?;
-    }
+    let (post_template, image_template) = builtin_templates(settings.output_profile);
 
-    Ok(post)
+    let mut env = Environment::new();
+    // Posts are Markdown, so nothing is HTML-escaped, whatever the template is called
+    env.set_auto_escape_callback(|_| AutoEscape::None);
+    env.add_filter("date", format_date);
+    env.add_filter("yaml", |value: JinjaValue| {
+        frontmatter::to_yaml(&value).map_err(template_error)
+    });
+    env.add_filter("toml", |value: JinjaValue| {
+        frontmatter::to_toml(&value).map_err(template_error)
+    });
+    env.add_function("image", render_image);
+    env.add_template_owned("post", load(&settings.post_template, post_template)?)?;
+    env.add_template_owned("image", load(&settings.image_template, image_template)?)?;
+    Ok(env)
 }
 
-fn image_to_markdown(image: &Image) -> String {
-    format!(
-        r#"<a href="{}{}"><img src="{}{}" width="{}" height="{}"></a>"#,
-        &JEKYL_SITE_URL,
-        image.relative_path,
-        &JEKYL_SITE_URL,
-        image.thumbnail.relative_path,
-        image.thumbnail.width,
-        image.thumbnail.height
-    )
+fn render(
+    templates: &Environment,
+    profile: OutputProfile,
+    post: &PostInfo,
+) -> Result<String, Mishap> {
+    let image = templates.get_template("image")?;
+    let markup = post
+        .attachments
+        .iter()
+        .map(|attachment| image.render(context! { image => attachment }))
+        .collect::<Result<Vec<String>, _>>()?;
+
+    let (content, gallery) = match &post.content {
+        Some(text) => {
+            let (text, unplaced) = place_inline_images(text, &post.attachments, &markup);
+            (Some(text), unplaced)
+        }
+        None => (None, post.attachments.iter().collect()),
+    };
+
+    let front_matter = frontmatter::for_profile(post, profile);
+    let markdown = templates
+        .get_template("post")?
+        .render(context! { post, front_matter, content, gallery })?;
+    Ok(markdown)
 }
 
-fn post_meta(post: &PostInfo) -> String {
-    format!(
-        r#"---
-title: |
-    {}
-author: {}
-date: {}
-permalink: {}
-layout: post
-comments: true
----"#,
-        post.title,
-        post.author,
-        post.date.format("%Y-%m-%d %H:%M"),
-        post.permalink,
-    )
+fn render_image(state: &State, image: JinjaValue) -> Result<String, minijinja::Error> {
+    state
+        .env()
+        .get_template("image")?
+        .render(context! { image })
+}
+
+/// Format a date with a chrono (strftime) format, e.g., `post.date | date("%Y-%m-%d")`.
+fn format_date(value: String, format: String) -> Result<String, minijinja::Error> {
+    DateTime::parse_from_rfc3339(&value)
+        .map(|date| date.format(&format).to_string())
+        .map_err(|err| template_error(err.to_string()))
+}
+
+fn template_error(message: String) -> minijinja::Error {
+    minijinja::Error::new(ErrorKind::InvalidOperation, message)
+}
+
+/// Replace `cid:` image references in the text with the markup for the images they refer to,
+/// returning the text and the images that were not referenced.
+fn place_inline_images<'a>(
+    text: &str,
+    images: &'a [Image],
+    markup: &[String],
+) -> (String, Vec<&'a Image>) {
+    let pattern =
+        Regex::new(r#"!\[[^\]]*\]\(cid:([^)\s]+)[^)]*\)|<img[^>]*src="cid:([^"]+)"[^>]*>"#)
+            .unwrap();
+
+    let mut placed = Vec::new();
+    let text = pattern.replace_all(text, |caps: &Captures| {
+        let cid = caps
+            .get(1)
+            .or_else(|| caps.get(2))
+            .map_or("", |m| m.as_str());
+        match images
+            .iter()
+            .position(|image| image.content_id.as_deref() == Some(cid))
+        {
+            Some(index) => {
+                placed.push(index);
+                markup[index].clone()
+            }
+            None => caps[0].to_string(),
+        }
+    });
+
+    let unplaced = images
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !placed.contains(index))
+        .map(|(_, image)| image)
+        .collect();
+
+    (text.to_string(), unplaced)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
 
-    #[test]
-    fn test_image_markdown() {
-        let img = Image {
+    fn profile_templates(profile: &str) -> Environment<'static> {
+        let settings = Settings::for_test(&[&format!("--output-profile={}", profile)]);
+        templates(&settings).unwrap()
+    }
+
+    fn default_templates() -> Environment<'static> {
+        profile_templates("jekyll")
+    }
+
+    fn image(name: &str, content_id: Option<&str>) -> Image {
+        Image {
             file: PathBuf::new(),
-            relative_path: "/foo.jpg".to_string(),
+            relative_path: format!("/{}.jpg", name),
+            url: format!("/{}.jpg", name),
             mimetype: "image/jpg".to_string(),
+            content_id: content_id.map(String::from),
             thumbnail: Thumbnail {
                 file: PathBuf::new(),
-                relative_path: "/foo_thumb.jpg".to_string(),
+                relative_path: format!("/{}_thumb.jpg", name),
+                url: format!("/{}_thumb.jpg", name),
                 width: 640,
                 height: 320,
             },
-        };
+        }
+    }
+
+    fn post(content: Option<&str>, attachments: Vec<Image>) -> PostInfo {
+        PostInfo::new(
+            "Lunch".to_string(),
+            "Alice".to_string(),
+            content.map(String::from),
+            Utc.with_ymd_and_hms(2020, 1, 15, 21, 26, 0).unwrap(),
+            "/lunch".to_string(),
+            attachments,
+            "_posts/2020-01-15-lunch.md".to_string(),
+            PathBuf::new(),
+            Vec::new(),
+            None,
+        )
+    }
+
+    const FOO_MARKUP: &str = r#"<a href="{{ site.url }}/foo.jpg"><img src="{{ site.url }}/foo_thumb.jpg" width="640" height="320"></a>"#;
+
+    #[test]
+    fn test_image_markdown() {
+        let templates = default_templates();
+        let template = templates.get_template("image").unwrap();
+        let markup = template
+            .render(context! { image => image("foo", None) })
+            .unwrap();
+        assert_eq!(FOO_MARKUP, markup);
+    }
+
+    #[test]
+    fn test_default_post() {
+        let post = post(Some("We ate well."), vec![image("foo", None)]);
+        let expected = format!(
+            "---\ntitle: Lunch\nauthor: Alice\ndate: 2020-01-15 21:26\npermalink: /lunch\nlayout: post\ncomments: true\n---\n\nWe ate well.\n\n{}\n\n",
+            FOO_MARKUP
+        );
+        assert_eq!(
+            expected,
+            render(&default_templates(), OutputProfile::Jekyll, &post).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_post_with_directives() {
+        let mut post = post(None, Vec::new());
+        post.front_matter = vec![
+            ("layout".to_string(), Value::from("photo")),
+            ("tags".to_string(), Value::from(vec!["travel", "food"])),
+        ];
+
+        let expected = "---\ntitle: Lunch\nauthor: Alice\ndate: 2020-01-15 21:26\npermalink: /lunch\nlayout: photo\ncomments: true\ntags:\n- travel\n- food\n---\n\n";
+        assert_eq!(
+            expected,
+            render(&default_templates(), OutputProfile::Jekyll, &post).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_inline_images() {
+        let images = vec![
+            image("a", Some("a@example.org")),
+            image("b", Some("b@example.org")),
+            image("c", None),
+        ];
+        let markup = vec!["A".to_string(), "B".to_string(), "C".to_string()];
+        let text = "Before\n\n![](cid:a@example.org)\n\nAfter";
+
+        let (text, unplaced) = place_inline_images(text, &images, &markup);
+
+        assert_eq!("Before\n\nA\n\nAfter", text);
+        let unplaced: Vec<&str> = unplaced.iter().map(|i| i.relative_path.as_str()).collect();
+        assert_eq!(vec!["/b.jpg", "/c.jpg"], unplaced);
+    }
+
+    #[test]
+    fn test_inline_html_image() {
+        let images = vec![image("a", Some("part1.abc"))];
+        let markup = vec!["A".to_string()];
+        let text = r#"<img src="cid:part1.abc" width="600" height="400">"#;
+        let (text, unplaced) = place_inline_images(text, &images, &markup);
+        assert_eq!("A", text);
+        assert!(unplaced.is_empty());
+    }
+
+    #[test]
+    fn test_inline_image_not_in_gallery() {
+        let post = post(Some("![](cid:foo)"), vec![image("foo", Some("foo"))]);
+        let markdown = render(&default_templates(), OutputProfile::Jekyll, &post).unwrap();
+        assert!(markdown.ends_with(&format!("---\n\n{}\n\n", FOO_MARKUP)));
+    }
+
+    fn tagged_post() -> PostInfo {
+        let mut post = post(Some("We ate well."), vec![image("foo", None)]);
+        post.permalink = "/lunch/".to_string();
+        post.front_matter = vec![
+            ("tags".to_string(), Value::from(vec!["travel", "food"])),
+            ("layout".to_string(), Value::from("note")),
+        ];
+        post
+    }
+
+    #[test]
+    fn test_hugo_post() {
+        let expected = r#"+++
+title = "Lunch"
+author = "Alice"
+date = 2020-01-15T21:26:00Z
+url = "/lunch/"
+tags = ["travel", "food"]
+layout = "note"
++++
+
+We ate well.
+
+{{< figure src="/foo_thumb.jpg" link="/foo.jpg" width="640" height="320" >}}
+
+"#;
+        let markdown = render(
+            &profile_templates("hugo"),
+            OutputProfile::Hugo,
+            &tagged_post(),
+        )
+        .unwrap();
+        assert_eq!(expected, markdown);
+    }
+
+    #[test]
+    fn test_zola_post() {
+        let expected = r#"+++
+title = "Lunch"
+date = 2020-01-15T21:26:00Z
+path = "/lunch/"
+
+[taxonomies]
+tags = ["travel", "food"]
+
+[extra]
+author = "Alice"
+layout = "note"
++++
+
+We ate well.
+
+[![](/foo_thumb.jpg)](/foo.jpg)
+
+"#;
+        let markdown = render(
+            &profile_templates("zola"),
+            OutputProfile::Zola,
+            &tagged_post(),
+        )
+        .unwrap();
+        assert_eq!(expected, markdown);
+    }
+
+    #[test]
+    fn test_eleventy_post() {
+        let expected = r#"---
+title: Lunch
+author: Alice
+date: 2020-01-15T21:26:00Z
+permalink: /lunch/
+layout: note
+tags:
+- travel
+- food
+---
+
+We ate well.
 
-        let markdown = r#"<a href="{{ site.url }}/foo.jpg"><img src="{{ site.url }}/foo_thumb.jpg" width="640" height="320"></a>"#;
+<a href="/foo.jpg"><img src="/foo_thumb.jpg" width="640" height="320"></a>
 
-        assert_eq!(markdown, image_to_markdown(&img));
+"#;
+        let markdown = render(
+            &profile_templates("eleventy"),
+            OutputProfile::Eleventy,
+            &tagged_post(),
+        )
+        .unwrap();
+        assert_eq!(expected, markdown);
     }
 }
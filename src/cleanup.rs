@@ -0,0 +1,118 @@
+use regex::Regex;
+
+use super::settings::CleanupRule;
+
+/// Remove replies, footers and anything matching the user's own patterns from the post text.
+pub fn apply(rules: &[CleanupRule], patterns: &[Regex], text: String) -> String {
+    let builtin = rules.iter().map(|rule| pattern(*rule));
+    let cleaned = builtin
+        .chain(patterns.iter().cloned())
+        .fold(text, |text, pattern| {
+            pattern.replace_all(&text, "").to_string()
+        });
+
+    // Removed lines can leave a run of blank lines behind
+    let blank_lines = Regex::new(r"\n\s*\n(\s*\n)+").unwrap();
+    blank_lines.replace_all(&cleaned, "\n\n").to_string()
+}
+
+fn pattern(rule: CleanupRule) -> Regex {
+    let pattern = match rule {
+        // Gmail and Apple Mail wrap long "On ... wrote:" lines, so allow for one break.
+        // The line must introduce a quote, so "On the train I wrote:" followed by a poem is kept.
+        CleanupRule::ReplyHeader => {
+            r"(?m)^(On\s[^\n]*(\n[^\n]*)?\swrote:[ \t]*\r?\n(\s*\n)*>|-{2,}\s*Original Message\s*-{2,}[ \t]*$)(?s).*"
+        }
+        CleanupRule::QuotedLines => r"(?m)^>.*(\n|$)",
+        // Only at the end of the text, so "Sent from the top of Ben Nevis" mid-post is kept
+        CleanupRule::MobileFooter => {
+            r"(?m)^(Sent from (my )?\S+( \S+){0,4}|Get Outlook for (iOS|Android))(\s*<[^>\n]*>)?\s*\z"
+        }
+    };
+    Regex::new(pattern).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(rule: CleanupRule, text: &str) -> String {
+        apply(&[rule], &[], text.to_string())
+    }
+
+    #[test]
+    fn test_reply_header() {
+        let input = "Looks good to me.\n\nOn Tue, 7 Jan 2020 at 10:00, Alice <alice@example.org> wrote:\n> Shall we go?\n";
+        assert_eq!(
+            "Looks good to me.\n\n",
+            clean(CleanupRule::ReplyHeader, input)
+        );
+    }
+
+    #[test]
+    fn test_wrapped_reply_header() {
+        let input = "Yes!\n\nOn 7 Jan 2020, at 10:00, Alice Liddell\n<alice@example.org> wrote:\n\n> Shall we go?\n";
+        assert_eq!("Yes!\n\n", clean(CleanupRule::ReplyHeader, input));
+    }
+
+    #[test]
+    fn test_outlook_original_message() {
+        let input = "Agreed.\n\n-----Original Message-----\nFrom: Alice\nSubject: Plans\n";
+        assert_eq!("Agreed.\n\n", clean(CleanupRule::ReplyHeader, input));
+    }
+
+    #[test]
+    fn test_reply_header_needs_own_line() {
+        let input = "On the way home I wrote: a poem.\n";
+        assert_eq!(input, clean(CleanupRule::ReplyHeader, input));
+    }
+
+    #[test]
+    fn test_quoted_lines() {
+        let input = "I agree with this:\n> Quoted\n> text\nAnd more.\n";
+        assert_eq!(
+            "I agree with this:\nAnd more.\n",
+            clean(CleanupRule::QuotedLines, input)
+        );
+    }
+
+    #[test]
+    fn test_sent_from_iphone() {
+        let input = "A photo from the beach.\n\nSent from my iPhone\n";
+        assert_eq!(
+            "A photo from the beach.\n\n",
+            clean(CleanupRule::MobileFooter, input)
+        );
+    }
+
+    #[test]
+    fn test_get_outlook() {
+        let input = "Lunch.\n\nGet Outlook for Android<https://aka.ms/ghei36>\n";
+        assert_eq!("Lunch.\n\n", clean(CleanupRule::MobileFooter, input));
+    }
+
+    #[test]
+    fn test_sent_from_in_a_sentence() {
+        let input = "Sent from my hotel room, overlooking the harbour at dawn with a coffee.\n";
+        assert_eq!(input, clean(CleanupRule::MobileFooter, input));
+    }
+
+    #[test]
+    fn test_sent_from_mid_post() {
+        let input = "We climbed all day.\nSent from the top of Ben Nevis\nThe view was great.\n";
+        assert_eq!(input, clean(CleanupRule::MobileFooter, input));
+    }
+
+    #[test]
+    fn test_wrote_without_quote() {
+        let input = "On the train I wrote:\n\nRoses are red,\nthe train is late.\n";
+        assert_eq!(input, clean(CleanupRule::ReplyHeader, input));
+    }
+
+    #[test]
+    fn test_user_pattern() {
+        let patterns = vec![Regex::new(r"(?m)^Unsubscribe.*$").unwrap()];
+        let input = "News\n\nUnsubscribe here: https://example.org\n";
+        assert_eq!("News\n\n", apply(&[], &patterns, input.to_string()));
+    }
+}
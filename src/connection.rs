@@ -0,0 +1,110 @@
+use imap::{Client, ClientBuilder, Connection, ConnectionMode};
+use rustls_connector::rustls::{ClientConfig, RootCertStore};
+use rustls_connector::{rustls_native_certs, RustlsConnector};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+
+use super::mishaps::Mishap;
+use super::settings::{ImapTls, Settings};
+
+/// Connect to the IMAP server, secured as given by `--imap-tls`.
+/// A plaintext connection is refused unless the server is on this machine, or `--allow-plaintext` is set.
+pub fn connect(settings: &Settings) -> Result<Client<Connection>, Mishap> {
+    let host = settings.hostname.as_str();
+    let port = settings.port;
+
+    if settings.imap_tls == ImapTls::None && !settings.allow_plaintext && !is_loopback(host, port)?
+    {
+        return Err(Mishap::PlaintextRefused(host.to_string()));
+    }
+
+    let client = match (&settings.imap_ca_file, settings.imap_tls) {
+        (_, ImapTls::None) => ClientBuilder::new(host, port)
+            .mode(ConnectionMode::Plaintext)
+            .connect()?,
+        (None, ImapTls::Implicit) => ClientBuilder::new(host, port)
+            .mode(ConnectionMode::Tls)
+            .connect()?,
+        (None, ImapTls::Starttls) => ClientBuilder::new(host, port)
+            .mode(ConnectionMode::StartTls)
+            .connect()?,
+        (Some(ca_file), tls) => connect_with_ca(host, port, ca_file, tls)?,
+    };
+
+    Ok(client)
+}
+
+// The imap crate only trusts the system roots, so with a CA file we do the TLS handshake ourselves
+fn connect_with_ca(
+    host: &str,
+    port: u16,
+    ca_file: &Path,
+    tls: ImapTls,
+) -> Result<Client<Connection>, Mishap> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
+        let _ = roots.add(cert);
+    }
+    let mut pem = BufReader::new(File::open(ca_file)?);
+    for cert in rustls_pemfile::certs(&mut pem) {
+        roots
+            .add(cert?)
+            .map_err(|err| Mishap::Tls(format!("{:?}: {}", ca_file, err)))?;
+    }
+
+    let connector: RustlsConnector = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+        .into();
+
+    let mut tcp = TcpStream::connect((host, port))?;
+
+    let greeting_read = tls == ImapTls::Starttls;
+    if greeting_read {
+        starttls(&mut tcp)?;
+    }
+
+    let stream = connector.connect(host, tcp).map_err(imap::Error::from)?;
+    let mut client = Client::new(Box::new(stream) as Connection);
+    if greeting_read {
+        client.greeting_read = true;
+    } else {
+        client.read_greeting()?;
+    }
+
+    Ok(client)
+}
+
+// Ask for the plain connection to be upgraded (RFC 3501, section 6.2.1), reading the greeting on the way
+fn starttls(tcp: &mut TcpStream) -> Result<(), Mishap> {
+    let _greeting = read_line(tcp)?;
+    tcp.write_all(b"a0 STARTTLS\r\n")?;
+    loop {
+        let line = read_line(tcp)?;
+        if let Some(status) = line.strip_prefix("a0 ") {
+            return if status.starts_with("OK") {
+                Ok(())
+            } else {
+                Err(Mishap::Tls(format!("STARTTLS refused: {}", status.trim())))
+            };
+        }
+    }
+}
+
+// One byte at a time, so nothing after the line is consumed before the TLS handshake
+fn read_line(tcp: &mut TcpStream) -> Result<String, std::io::Error> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\n") {
+        tcp.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+fn is_loopback(host: &str, port: u16) -> Result<bool, Mishap> {
+    let addrs: Vec<_> = (host, port).to_socket_addrs()?.collect();
+    Ok(!addrs.is_empty() && addrs.iter().all(|addr| addr.ip().is_loopback()))
+}
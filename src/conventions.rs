@@ -4,10 +4,13 @@ use std::io::Error;
 use std::path::Path;
 use std::path::PathBuf;
 
+use super::settings::OutputProfile;
+
 #[derive(Debug)]
 pub struct FileConventions {
     post_media_dir: PathBuf,
     post_media_url: String,
+    post_media_link: String,
     permalink: String,
     stem: String,
     post_path: String,
@@ -19,42 +22,31 @@ impl FileConventions {
         output_dir: &Path,
         media_path: &str,
         posts_path: &str,
+        profile: OutputProfile,
         date: &DateTime<Utc>,
         slug: &str,
     ) -> Result<FileConventions, Error> {
-        // Media (i.e., images) will be in seperate yearly subdirectories:
-        let mut post_media_dir = output_dir.to_path_buf();
-        post_media_dir.push(media_path);
-        let year = date.format("%Y").to_string();
-        post_media_dir.push(&year);
-
-        if !post_media_dir.exists() {
-            fs::create_dir(&post_media_dir)?;
+        let conventions = match profile {
+            OutputProfile::Jekyll | OutputProfile::Eleventy => {
+                dated_files(output_dir, media_path, posts_path, date, slug)
+            }
+            OutputProfile::Hugo | OutputProfile::Zola => page_bundle(output_dir, posts_path, slug),
+        };
+
+        // The permalink is the slug, with no date information.
+        // Jekyll adds the extension itself; the others need a trailing slash to make a directory.
+        let permalink = match profile {
+            OutputProfile::Jekyll => format!("/{}", &slug),
+            _ => format!("/{}/", &slug),
+        };
+
+        if !conventions.post_media_dir.exists() {
+            fs::create_dir_all(&conventions.post_media_dir)?;
         }
 
-        // Corresponding URL:
-        let post_media_url = format!("/{}/{}", &media_path, &year);
-
-        // All filenames will start with this pattern:
-        let stem = format!("{}-{}", date.format("%Y-%m-%d"), slug);
-
-        // The blog post is a single filename:
-        let mut post_filename = output_dir.to_path_buf();
-        post_filename.push(posts_path);
-        post_filename.push(format!("{}.md", stem));
-
-        let post_path = format!("{}/{}.md", &posts_path, stem);
-
-        // The permalink is the slug, with no date information:
-        let permalink = format!("/{}", &slug);
-
         Ok(FileConventions {
-            post_media_dir,
-            post_media_url,
             permalink,
-            stem,
-            post_path,
-            post_filename,
+            ..conventions
         })
     }
 
@@ -80,6 +72,11 @@ impl FileConventions {
         format!("{}/{}-{}.{}", &self.post_media_url, self.stem, count, "jpg")
     }
 
+    /// How the post links to the image
+    pub fn attachment_link(&self, count: usize) -> String {
+        format!("{}{}-{}.{}", &self.post_media_link, self.stem, count, "jpg")
+    }
+
     pub fn attachment_thumb_path(&self, count: usize) -> PathBuf {
         let mut image_file = self.post_media_dir.clone();
         image_file.push(format!("{}-{}-thumb.{}", self.stem, count, "jpg"));
@@ -92,4 +89,109 @@ impl FileConventions {
             &self.post_media_url, self.stem, count, "jpg"
         )
     }
+
+    /// How the post links to the thumbnail
+    pub fn attachment_thumb_link(&self, count: usize) -> String {
+        format!(
+            "{}{}-{}-thumb.{}",
+            &self.post_media_link, self.stem, count, "jpg"
+        )
+    }
+}
+
+/// Posts named by date and slug, with media in separate yearly subdirectories.
+fn dated_files(
+    output_dir: &Path,
+    media_path: &str,
+    posts_path: &str,
+    date: &DateTime<Utc>,
+    slug: &str,
+) -> FileConventions {
+    let year = date.format("%Y").to_string();
+    let mut post_media_dir = output_dir.to_path_buf();
+    post_media_dir.push(media_path);
+    post_media_dir.push(&year);
+
+    // Corresponding URL:
+    let post_media_url = format!("/{}/{}", &media_path, &year);
+
+    // All filenames will start with this pattern:
+    let stem = format!("{}-{}", date.format("%Y-%m-%d"), slug);
+
+    // The blog post is a single filename:
+    let mut post_filename = output_dir.to_path_buf();
+    post_filename.push(posts_path);
+    post_filename.push(format!("{}.md", stem));
+
+    let post_path = format!("{}/{}.md", &posts_path, stem);
+
+    FileConventions {
+        post_media_link: format!("{}/", post_media_url),
+        post_media_dir,
+        post_media_url,
+        permalink: String::new(),
+        stem,
+        post_path,
+        post_filename,
+    }
+}
+
+/// A directory per post, holding `index.md` and its images, which the post links to relatively.
+fn page_bundle(output_dir: &Path, posts_path: &str, slug: &str) -> FileConventions {
+    let mut post_media_dir = output_dir.to_path_buf();
+    post_media_dir.push(posts_path);
+    post_media_dir.push(slug);
+
+    FileConventions {
+        post_filename: post_media_dir.join("index.md"),
+        post_media_dir,
+        post_media_url: format!("/{}/{}", posts_path, slug),
+        post_media_link: String::new(),
+        permalink: String::new(),
+        stem: slug.to_string(),
+        post_path: format!("{}/{}/index.md", posts_path, slug),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn conventions(dir: &Path, profile: OutputProfile) -> FileConventions {
+        let date = Utc.with_ymd_and_hms(2020, 1, 15, 21, 26, 0).unwrap();
+        FileConventions::new(dir, "media", "posts", profile, &date, "lunch").unwrap()
+    }
+
+    #[test]
+    fn test_jekyll_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let conventions = conventions(dir.path(), OutputProfile::Jekyll);
+        assert_eq!("posts/2020-01-15-lunch.md", conventions.post_path());
+        assert_eq!("/lunch", conventions.permalink());
+        assert_eq!(
+            "/media/2020/2020-01-15-lunch-0.jpg",
+            conventions.attachment_url(0)
+        );
+        assert_eq!(
+            "/media/2020/2020-01-15-lunch-0-thumb.jpg",
+            conventions.attachment_thumb_link(0)
+        );
+        assert!(dir.path().join("media/2020").is_dir());
+    }
+
+    #[test]
+    fn test_page_bundle_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let conventions = conventions(dir.path(), OutputProfile::Hugo);
+        assert_eq!("posts/lunch/index.md", conventions.post_path());
+        assert_eq!("/lunch/", conventions.permalink());
+        assert_eq!("/posts/lunch/lunch-0.jpg", conventions.attachment_url(0));
+        assert_eq!("lunch-0.jpg", conventions.attachment_link(0));
+        assert_eq!(
+            dir.path().join("posts/lunch/lunch-0-thumb.jpg"),
+            conventions.attachment_thumb_path(0)
+        );
+        assert!(dir.path().join("posts/lunch").is_dir());
+    }
 }
@@ -0,0 +1,338 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use serde_yaml_ng::{Mapping, Value};
+
+use super::mishaps::Mishap;
+use super::sanitise;
+
+/// Front matter the author set at the top of the email body: either a `---` YAML block,
+/// or a first paragraph of `Key: value` lines.
+#[derive(Debug, Default, PartialEq)]
+pub struct Directives {
+    pub fields: Vec<(String, Value)>,
+}
+
+/// The keys a paragraph of `Key: value` lines may use to count as directives rather than prose.
+const KNOWN_KEYS: [&str; 12] = [
+    "title",
+    "author",
+    "date",
+    "permalink",
+    "layout",
+    "tags",
+    "categories",
+    "category",
+    "excerpt",
+    "description",
+    "image",
+    "comments",
+];
+
+/// Keys holding a list, which may be written as `a, b, c`.
+const LIST_KEYS: [&str; 2] = ["tags", "categories"];
+
+impl Directives {
+    /// A field with a single value (text, number or true/false), as text.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match self.value(key)? {
+            Value::String(text) => Some(text.clone()),
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    fn value(&self, key: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    /// The date directive, if there is one.
+    pub fn date(&self) -> Result<Option<DateTime<Utc>>, Mishap> {
+        match self.get("date") {
+            None => Ok(None),
+            Some(value) => parse_date(&value)
+                .map(Some)
+                .ok_or_else(|| Mishap::EmailField(format!("Date: {}", value))),
+        }
+    }
+
+    /// Set a field, unless the author already set it.
+    pub fn set_default(&mut self, key: &str, value: Value) {
+        if self.value(key).is_none() {
+            self.fields.push((key.to_string(), value));
+        }
+    }
+
+    /// Add items to a list field, such as the tags.
+    pub fn extend_list(&mut self, key: &str, items: &[String]) {
+        if items.is_empty() {
+            return;
+        }
+
+        let mut all = match self.value(key) {
+            Some(Value::Sequence(existing)) => existing.clone(),
+            Some(Value::Null) | None => Vec::new(),
+            Some(single) => vec![single.clone()],
+        };
+        for item in items {
+            let item = Value::String(item.clone());
+            if !all.contains(&item) {
+                all.push(item);
+            }
+        }
+
+        let value = Value::Sequence(all);
+        match self
+            .fields
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        {
+            Some((_, old)) => *old = value,
+            None => self.fields.push((key.to_string(), value)),
+        }
+    }
+
+    /// Remove fields holding template code, which some generators run
+    /// (such as an Eleventy `permalink` or `eleventyComputed`). Returns the keys removed.
+    pub fn remove_template_code(&mut self) -> Vec<String> {
+        let (code, plain) = std::mem::take(&mut self.fields)
+            .into_iter()
+            .partition(|(_, value)| has_template_code(value));
+        self.fields = plain;
+        code.into_iter().map(|(key, _)| key).collect()
+    }
+
+    /// Whether a paragraph of `Key: value` lines looks like directives: every key is a known one,
+    /// and any date is a date. So "Date: Saturday" followed by "Time: 7pm" is left in the post.
+    fn is_plausible(&self) -> bool {
+        let known = self
+            .fields
+            .iter()
+            .all(|(key, _)| KNOWN_KEYS.contains(&key.as_str()));
+        known && self.date().is_ok()
+    }
+
+    /// Every field apart from the given keys.
+    pub fn without(self, keys: &[&str]) -> Vec<(String, Value)> {
+        self.fields
+            .into_iter()
+            .filter(|(k, _)| !keys.iter().any(|key| k.eq_ignore_ascii_case(key)))
+            .collect()
+    }
+}
+
+fn has_template_code(value: &Value) -> bool {
+    match value {
+        Value::String(text) => sanitise::has_template_syntax(text),
+        Value::Sequence(items) => items.iter().any(has_template_code),
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .any(|(key, value)| has_template_code(key) || has_template_code(value)),
+        Value::Tagged(tagged) => has_template_code(&tagged.value),
+        _ => false,
+    }
+}
+
+/// Split the directives from the rest of the text.
+/// Text that does not start with directives is returned unchanged.
+pub fn extract(text: &str) -> (Directives, String) {
+    let lines: Vec<&str> = text.trim_start_matches(['\r', '\n']).lines().collect();
+
+    if lines.first().map(|line| line.trim_end()) == Some("---") {
+        let end = lines[1..].iter().position(|line| line.trim_end() == "---");
+        if let Some(end) = end {
+            if let Some(fields) = yaml_block(&lines[1..=end]) {
+                return (Directives { fields }, lines[end + 2..].join("\n"));
+            }
+        }
+    }
+
+    let paragraph = lines
+        .iter()
+        .take_while(|line| !line.trim().is_empty())
+        .count();
+    let fields: Option<Vec<(String, Value)>> = lines[..paragraph]
+        .iter()
+        .map(|line| key_value(line))
+        .collect();
+
+    match fields.map(|fields| Directives { fields }) {
+        Some(directives) if directives.is_plausible() => {
+            (directives, lines[paragraph..].join("\n"))
+        }
+        _ => (Directives::default(), text.to_string()),
+    }
+}
+
+/// A `Key: value` line, with the key lower-cased.
+/// Lists are written `a, b, c`; other values are text, unless they are numbers or true/false.
+fn key_value(line: &str) -> Option<(String, Value)> {
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim().to_lowercase();
+    let value = value.trim();
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_key || value.is_empty() {
+        return None;
+    }
+
+    let value = if LIST_KEYS.contains(&key.as_str()) {
+        let items = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Value::String(item.to_string()));
+        Value::Sequence(items.collect())
+    } else {
+        scalar(value)
+    };
+    Some((key, value))
+}
+
+/// A single value: text, unless it is a number or true/false.
+pub fn scalar(text: &str) -> Value {
+    match serde_yaml_ng::from_str(text) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_) | Value::String(_))) => value,
+        _ => Value::String(text.to_string()),
+    }
+}
+
+/// The top-level fields of a YAML block, or None if it is not a YAML mapping.
+fn yaml_block(lines: &[&str]) -> Option<Vec<(String, Value)>> {
+    let mapping: Mapping = serde_yaml_ng::from_str(&lines.join("\n")).ok()?;
+    let fields = mapping
+        .into_iter()
+        .filter_map(|(key, value)| match key {
+            Value::String(key) => Some((key, value)),
+            _ => None,
+        })
+        .collect();
+    Some(fields)
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|date| Utc.from_utc_datetime(&date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(key: &str, yaml: &str) -> (String, Value) {
+        (key.to_string(), serde_yaml_ng::from_str(yaml).unwrap())
+    }
+
+    #[test]
+    fn test_key_value_lines() {
+        let text = "Tags: travel, food\nLayout: photo\nComments: false\nTitle: Lunch: the sequel\n\nWe ate well.";
+        let (directives, content) = extract(text);
+        assert_eq!(
+            vec![
+                field("tags", "[travel, food]"),
+                field("layout", "photo"),
+                field("comments", "false"),
+                field("title", "'Lunch: the sequel'"),
+            ],
+            directives.fields
+        );
+        assert_eq!("\nWe ate well.", content);
+    }
+
+    #[test]
+    fn test_yaml_block() {
+        let text =
+            "---\ntitle: \"Lunch\"\ntags:\n  - travel\n  - food\nmood: happy\n---\nWe ate well.";
+        let (directives, content) = extract(text);
+        assert_eq!(
+            vec![
+                field("title", "Lunch"),
+                field("tags", "[travel, food]"),
+                field("mood", "happy"),
+            ],
+            directives.fields
+        );
+        assert_eq!(Some("Lunch".to_string()), directives.get("Title"));
+        assert_eq!("We ate well.", content);
+    }
+
+    #[test]
+    fn test_invalid_yaml_block_kept() {
+        let text = "---\ntitle: [unclosed\n---\nWe ate well.";
+        let (directives, content) = extract(text);
+        assert!(directives.fields.is_empty());
+        assert_eq!(text, content);
+    }
+
+    #[test]
+    fn test_prose_is_not_directives() {
+        let text = "Note: the cafe was closed.\n\nSo we went home.";
+        let (directives, content) = extract(text);
+        assert!(directives.fields.is_empty());
+        assert_eq!(text, content);
+    }
+
+    #[test]
+    fn test_event_details_are_not_directives() {
+        for text in [
+            "Date: Saturday\nTime: 7pm\n\nSee you at the pub",
+            "Date: Saturday\n\nSee you at the pub",
+            "Title: Quiz night\nVenue: The Crown\n\nSee you there",
+        ] {
+            let (directives, content) = extract(text);
+            assert!(directives.fields.is_empty());
+            assert_eq!(text, content);
+        }
+    }
+
+    #[test]
+    fn test_date() {
+        let (directives, _) = extract("Date: 2020-01-15 21:26\n\nText");
+        let expected = Utc.with_ymd_and_hms(2020, 1, 15, 21, 26, 0).single();
+        assert_eq!(expected, directives.date().unwrap());
+
+        let (directives, _) = extract("---\ndate: someday\n---\nText");
+        assert!(directives.date().is_err());
+    }
+
+    #[test]
+    fn test_extend_list() {
+        let (mut directives, _) = extract("Tags: travel, food\n\nText");
+        directives.extend_list("tags", &["food".to_string(), "cafe".to_string()]);
+        directives.set_default("layout", Value::from("note"));
+        directives.set_default("tags", Value::from("ignored"));
+        assert_eq!(
+            vec![
+                field("tags", "[travel, food, cafe]"),
+                field("layout", "note"),
+            ],
+            directives.fields
+        );
+    }
+
+    #[test]
+    fn test_remove_template_code() {
+        let text = "---\ntitle: Lunch\npermalink: \"{{ 'x' | shell }}\"\neleventyComputed:\n  title: \"{% include x %}\"\n---\nText";
+        let (mut directives, _) = extract(text);
+        assert_eq!(
+            vec!["permalink", "eleventyComputed"],
+            directives.remove_template_code()
+        );
+        assert_eq!(vec![field("title", "Lunch")], directives.fields);
+    }
+}
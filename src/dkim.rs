@@ -0,0 +1,434 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::Regex;
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ED25519, RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// DKIM public keys, as they would be published in DNS, keyed by `selector._domainkey.domain`.
+///
+/// The key file has one record per line, the name followed by the TXT record:
+/// `brisbane._domainkey.example.com v=DKIM1; k=ed25519; p=11qYAYKx...`
+/// Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    records: HashMap<String, String>,
+}
+
+impl KeyStore {
+    pub fn load(path: &Path) -> Result<KeyStore, std::io::Error> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(KeyStore::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> KeyStore {
+        let records = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .map(|(name, record)| (name.to_lowercase(), record.trim().to_string()))
+            .collect();
+        KeyStore { records }
+    }
+
+    fn lookup(&self, selector: &str, domain: &str) -> Option<&String> {
+        let name = format!("{}._domainkey.{}", selector, domain).to_lowercase();
+        self.records.get(&name)
+    }
+}
+
+/// The result of checking one `DKIM-Signature` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The signature is valid for the signing domain (the `d=` tag).
+    Pass(String),
+    /// The signature could not be verified, with the reason.
+    Fail(String),
+}
+
+/// Verify every DKIM signature in a raw message against the key store.
+pub fn verify(raw_message: &[u8], keys: &KeyStore) -> Vec<Verdict> {
+    let (headers, body) = split_message(raw_message);
+
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("DKIM-Signature"))
+        .map(
+            |(_, signature)| match verify_signature(signature, &headers, body, keys) {
+                Ok(domain) => Verdict::Pass(domain),
+                Err(reason) => Verdict::Fail(reason),
+            },
+        )
+        .collect()
+}
+
+fn verify_signature(
+    signature: &str,
+    headers: &[(String, String)],
+    body: &[u8],
+    keys: &KeyStore,
+) -> Result<String, String> {
+    let tags = tag_list(value_of(signature));
+    let tag = |name: &str| {
+        tags.get(name)
+            .map(String::as_str)
+            .ok_or(format!("missing {}= tag", name))
+    };
+
+    let domain = tag("d")?;
+    let selector = tag("s")?;
+    let algorithm = tag("a")?;
+    let signed_headers = tag("h")?;
+    let body_hash = decode(tag("bh")?)?;
+    let sig = decode(tag("b")?)?;
+
+    if tag("v")? != "1" {
+        return Err("unsupported version".to_string());
+    }
+
+    // RFC 6376 section 6.1.1: a signature that does not cover From must be ignored
+    if !signed_headers
+        .split(':')
+        .any(|name| name.trim().eq_ignore_ascii_case("from"))
+    {
+        return Err("From header not signed".to_string());
+    }
+
+    // A body length limit would let anyone append their own text to a signed message
+    if tags.contains_key("l") {
+        return Err("l= body length limit not accepted".to_string());
+    }
+
+    if let Some(expires) = tags.get("x") {
+        let expires: u64 = expires.parse().map_err(|_| "bad x= tag".to_string())?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        if now > expires {
+            return Err("signature expired".to_string());
+        }
+    }
+
+    let (header_canon, body_canon) = match tags.get("c").map(String::as_str) {
+        None => (Canon::Simple, Canon::Simple),
+        Some(c) => {
+            let (h, b) = c.split_once('/').unwrap_or((c, "simple"));
+            (Canon::parse(h)?, Canon::parse(b)?)
+        }
+    };
+
+    // Body
+    let canonical_body = body_canon.body(body);
+    if digest(&SHA256, &canonical_body).as_ref() != body_hash.as_slice() {
+        return Err("body hash did not verify".to_string());
+    }
+
+    // Headers, each instance taken from the bottom up, followed by the signature header itself
+    let mut remaining: Vec<&(String, String)> = headers.iter().collect();
+    let mut data = Vec::new();
+    for name in signed_headers.split(':').map(str::trim) {
+        let found = remaining
+            .iter()
+            .rposition(|(header, _)| header.eq_ignore_ascii_case(name));
+        if let Some(index) = found {
+            let (_, line) = remaining.remove(index);
+            data.extend(header_canon.header(line).bytes());
+        }
+    }
+    let unsigned = without_signature(signature);
+    let unsigned = header_canon.header(&unsigned);
+    data.extend(unsigned.trim_end_matches("\r\n").bytes());
+
+    // Signature
+    let record = keys
+        .lookup(selector, domain)
+        .ok_or(format!("no key for {}._domainkey.{}", selector, domain))?;
+    let key_tags = tag_list(record);
+    let public_key = decode(key_tags.get("p").map(String::as_str).unwrap_or(""))?;
+    if public_key.is_empty() {
+        return Err("key revoked".to_string());
+    }
+
+    let verified = match algorithm {
+        "rsa-sha256" => {
+            let rsa_key = rsa_public_key(&public_key).ok_or("malformed RSA key".to_string())?;
+            UnparsedPublicKey::new(&RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, rsa_key)
+                .verify(&data, &sig)
+        }
+        "ed25519-sha256" => {
+            let hash = digest(&SHA256, &data);
+            UnparsedPublicKey::new(&ED25519, &public_key).verify(hash.as_ref(), &sig)
+        }
+        other => return Err(format!("unsupported algorithm {}", other)),
+    };
+
+    verified
+        .map(|_| domain.to_lowercase())
+        .map_err(|_| "signature did not verify".to_string())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Canon {
+    Simple,
+    Relaxed,
+}
+
+impl Canon {
+    fn parse(name: &str) -> Result<Canon, String> {
+        match name {
+            "simple" => Ok(Canon::Simple),
+            "relaxed" => Ok(Canon::Relaxed),
+            other => Err(format!("unsupported canonicalization {}", other)),
+        }
+    }
+
+    /// Canonicalize a complete header line (name, colon, value and trailing CRLF).
+    fn header(&self, line: &str) -> String {
+        match self {
+            Canon::Simple => line.to_string(),
+            Canon::Relaxed => {
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                let unfolded = value.replace("\r\n", "");
+                let whitespace = Regex::new(r"[ \t]+").unwrap();
+                let value = whitespace.replace_all(&unfolded, " ");
+                format!("{}:{}\r\n", name.trim().to_lowercase(), value.trim())
+            }
+        }
+    }
+
+    fn body(&self, body: &[u8]) -> Vec<u8> {
+        let whitespace = regex::bytes::Regex::new(r"[ \t]+").unwrap();
+
+        let mut lines: Vec<Vec<u8>> = body
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .map(|line| match self {
+                Canon::Simple => line.to_vec(),
+                Canon::Relaxed => {
+                    let mut line = whitespace.replace_all(line, &b" "[..]).into_owned();
+                    if line.last() == Some(&b' ') {
+                        line.pop();
+                    }
+                    line
+                }
+            })
+            .collect();
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+
+        match (self, lines.is_empty()) {
+            (Canon::Relaxed, true) => Vec::new(),
+            (Canon::Simple, true) => b"\r\n".to_vec(),
+            (_, false) => {
+                let mut canonical = lines.join(&b"\r\n"[..]);
+                canonical.extend(b"\r\n");
+                canonical
+            }
+        }
+    }
+}
+
+/// Split a raw message into its header lines (name and full CRLF-terminated line) and body.
+fn split_message(raw: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut offset = 0;
+
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        offset += line.len();
+        let content = String::from_utf8_lossy(line);
+        let content = content.trim_end_matches(['\r', '\n']);
+        if content.is_empty() {
+            break;
+        }
+        let line = format!("{}\r\n", content);
+        match headers.last_mut() {
+            Some((_, previous)) if line.starts_with([' ', '\t']) => previous.push_str(&line),
+            _ => {
+                let name = content.split(':').next().unwrap_or("").trim().to_string();
+                headers.push((name, line));
+            }
+        }
+    }
+
+    (headers, &raw[offset..])
+}
+
+fn value_of(line: &str) -> &str {
+    line.split_once(':').map(|(_, value)| value).unwrap_or("")
+}
+
+/// Parse a `tag=value; tag=value` list, with all whitespace removed from values.
+fn tag_list(text: &str) -> HashMap<String, String> {
+    text.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(tag, value)| {
+            let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+            (tag.trim().to_string(), value)
+        })
+        .collect()
+}
+
+/// The signature header line with the value of its `b=` tag removed, as required for signing.
+fn without_signature(line: &str) -> String {
+    let pattern = Regex::new(r"(^|[;:\s])(b\s*=)[^;]*").unwrap();
+    pattern.replace(line, "$1$2").to_string()
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    STANDARD
+        .decode(value)
+        .map_err(|err| format!("bad base64: {}", err))
+}
+
+/// DKIM publishes RSA keys as a SubjectPublicKeyInfo, but ring wants the RSAPublicKey inside it.
+fn rsa_public_key(der: &[u8]) -> Option<&[u8]> {
+    let (tag, spki, _) = der_element(der)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (first_tag, _, rest) = der_element(spki)?;
+    if first_tag == 0x02 {
+        // Already a bare RSAPublicKey
+        return Some(der);
+    }
+    let (bit_string_tag, bits, _) = der_element(rest)?;
+    match (bit_string_tag, bits.split_first()) {
+        (0x03, Some((0, key))) => Some(key),
+        _ => None,
+    }
+}
+
+/// Read one DER element, returning its tag, its contents, and whatever follows it.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count > 4 || rest.len() < count {
+            return None;
+        }
+        let length = rest[..count]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        (length, &rest[count..])
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example message and keys from RFC 8463, appendix A
+    const SIGNED: &str = "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed;\r
+ d=football.example.com; i=@football.example.com;\r
+ q=dns/txt; s=brisbane; t=1528637909; h=from : to :\r
+ subject : date : message-id : from : subject : date;\r
+ bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r
+ b=/gCrinpcQOoIfuHNQIbq4pgh9kyIK3AQUdt9OdqQehSwhEIug4D11Bus\r
+ Fa3bT3FY5OsU7ZbnKELq+eXdp1Q1Dw==\r
+DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed;\r
+ d=football.example.com; i=@football.example.com;\r
+ q=dns/txt; s=test; t=1528637909; h=from : to : subject :\r
+ date : message-id : from : subject : date;\r
+ bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r
+ b=F45dVWDfMbQDGHJFlXUNB2HKfbCeLRyhDXgFpEL8GwpsRe0IeIixNTe3\r
+ DhCVlUrSjV4BwcVcOF6+FF3Zo9Rpo1tFOeS9mPYQTnGdaSGsgeefOsk2Jz\r
+ dA+L10TeYt9BgDfQNZtKdN1WO//KgIqXP7OdEFE4LjFYNcUxZQ4FADY+8=\r
+From: Joe SixPack <joe@football.example.com>\r
+To: Suzie Q <suzie@shopping.example.net>\r
+Subject: Is dinner ready?\r
+Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r
+Message-ID: <20030712040037.46341.5F8J@football.example.com>\r
+\r
+Hi.\r
+\r
+We lost the game.  Are you hungry yet?\r
+\r
+Joe.\r
+";
+
+    const KEYS: &str = "
+# RFC 8463 test keys
+brisbane._domainkey.football.example.com v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=
+test._domainkey.football.example.com v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDkHlOQoBTzWRiGs5V6NpP3idY6Wk08a5qhdR6wy5bdOKb2jLQiY/J16JYi0Qvx/byYzCNb3W91y3FutACDfzwQ/BC/e/8uBsCR+yz1Lxj+PL6lHvqMKrM3rG4hstT5QjvHO9PzoxZyVYLzBfO2EeC3Ip3G+2kryOTIKT+l/K4w3QIDAQAB
+";
+
+    #[test]
+    fn test_rfc8463_signatures() {
+        let keys = KeyStore::parse(KEYS);
+        let expected = vec![
+            Verdict::Pass("football.example.com".to_string()),
+            Verdict::Pass("football.example.com".to_string()),
+        ];
+        assert_eq!(expected, verify(SIGNED.as_bytes(), &keys));
+    }
+
+    #[test]
+    fn test_tampered_body() {
+        let keys = KeyStore::parse(KEYS);
+        let tampered = SIGNED.replace("hungry", "thirsty");
+        for verdict in verify(tampered.as_bytes(), &keys) {
+            assert_eq!(
+                Verdict::Fail("body hash did not verify".to_string()),
+                verdict
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejected_tags() {
+        let keys = KeyStore::parse(KEYS);
+        let reasons = |message: String| -> Vec<Verdict> { verify(message.as_bytes(), &keys) };
+        let fail = |reason: &str| {
+            vec![
+                Verdict::Fail(reason.to_string()),
+                Verdict::Fail(reason.to_string()),
+            ]
+        };
+
+        assert_eq!(
+            fail("From header not signed"),
+            reasons(SIGNED.replace("from : ", ""))
+        );
+        assert_eq!(
+            fail("l= body length limit not accepted"),
+            reasons(SIGNED.replace("t=1528637909;", "t=1528637909; l=10;"))
+        );
+        assert_eq!(
+            fail("signature expired"),
+            reasons(SIGNED.replace("t=1528637909;", "t=1528637909; x=1528641509;"))
+        );
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let verdicts = verify(SIGNED.as_bytes(), &KeyStore::default());
+        assert!(matches!(&verdicts[0], Verdict::Fail(reason) if reason.starts_with("no key")));
+    }
+
+    #[test]
+    fn test_relaxed_canonicalization() {
+        // Examples from RFC 6376, section 3.4.5
+        assert_eq!("a:X\r\n", Canon::Relaxed.header("A: X\r\n"));
+        assert_eq!("b:Y Z\r\n", Canon::Relaxed.header("B : Y\t\r\n\tZ  \r\n"));
+        assert_eq!(
+            b" C\r\nD E\r\n".to_vec(),
+            Canon::Relaxed.body(b" C \r\nD \t E\r\n\r\n\r\n")
+        );
+        assert_eq!(
+            b" C \r\nD \t E\r\n".to_vec(),
+            Canon::Simple.body(b" C \r\nD \t E\r\n\r\n\r\n")
+        );
+    }
+}
@@ -1,15 +1,26 @@
 use chrono::{DateTime, TimeZone, Utc};
-use imap::Session;
 use log::debug;
 use mailparse::*;
+use regex::Regex;
+use ring::digest::{digest, SHA256};
 
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::Path;
 
+use super::allowlist;
+use super::authentication;
 use super::blog::{Image, PostInfo, Thumbnail};
+use super::cleanup;
+use super::directives::{self, Directives};
+use super::flowed;
+use super::html;
+use super::postingtoken;
+use super::sanitise;
 use super::settings::Settings;
 use super::signatureblock;
+use super::state::StateStore;
+use super::subjectprefix;
 
 use super::conventions;
 use conventions::FileConventions;
@@ -18,79 +29,138 @@ use super::mishaps::Mishap;
 
 use super::image::thumbnail;
 
-pub fn fetch<T: Read + Write>(
-    settings: &Settings,
-    imap_session: &mut Session<T>,
-) -> Result<Option<String>, Mishap> {
-    // fetch message number 1 in this mailbox, along with its RFC822 field.
-    // RFC 822 dictates the format of the body of e-mails
-
-    let sequence_set = "1";
-    let messages = imap_session.fetch(sequence_set, "RFC822")?;
-    let message = if let Some(m) = messages.iter().next() {
-        m
-    } else {
-        return Ok(None);
-    };
-
-    // The body will be the mime content of the message (including heeader)
-    let body = message.body().expect("message did not have a body!");
-    let body = std::str::from_utf8(body)
-        .expect("message was not valid utf-8")
-        .to_string();
-
-    if settings.expunge {
-        imap_session.store(sequence_set, "+FLAGS (\\Seen \\Deleted)")?;
-        let _msg_sequence_numbers = imap_session.expunge()?;
-    }
-
-    Ok(Some(body))
-}
-
-pub fn parse(mime_msg: &str) -> Result<ParsedMail<'_>, Mishap> {
-    let bytes = mime_msg.as_bytes();
-    let result = mailparse::parse_mail(bytes)?;
+/// Parse a raw message. Bytes rather than text, as 8-bit messages need not be UTF-8:
+/// each part is decoded using its own charset.
+pub fn parse(mime_msg: &[u8]) -> Result<ParsedMail<'_>, Mishap> {
+    let result = mailparse::parse_mail(mime_msg)?;
     Ok(result)
 }
 
-pub fn extract(settings: &Settings, out_dir: &Path, mail: ParsedMail) -> Result<PostInfo, Mishap> {
+pub fn extract(
+    settings: &Settings,
+    out_dir: &Path,
+    state: &StateStore,
+    mail: ParsedMail,
+) -> Result<PostInfo, Mishap> {
     if settings.show_outline {
         // Debug output to show the structure of the MIME message
         outline(&mail);
     }
 
-    let sender: String = sender(&mail)?.unwrap_or_else(|| String::from("Someone"));
+    let from: Option<SingleInfo> = sender(&mail)?;
+    let address = from
+        .as_ref()
+        .map(|info| info.addr.clone())
+        .unwrap_or_default();
+    if !allowlist::permits(&settings.allow_senders, &address) {
+        return Err(Mishap::SenderRefused(address));
+    }
+    authentication::check(settings, &mail, &address)?;
+    // Without authentication anyone could claim to be a trusted sender
+    let trusted = authentication::verifies_sender(settings)
+        && allowlist::matches(&settings.trusted_senders, &address);
+
     let subject: Option<String> = mail.headers.get_first_value("Subject");
-    let content: Option<String> = body(&mail)?.map(signatureblock::remove);
-    let date: DateTime<Utc> = date(&mail)?.unwrap_or_else(Utc::now);
+    // Recipients are only needed to look for a plus-addressed posting token
+    let recipients = if settings.posting_tokens.is_empty() {
+        Vec::new()
+    } else {
+        recipients(&mail)
+    };
+    let (token, subject) = postingtoken::find(&settings.posting_tokens, &recipients, subject);
+    if token.is_none() && !settings.posting_tokens.is_empty() {
+        return Err(Mishap::MissingPostingToken);
+    }
+    let prefixes = if settings.subject_prefixes.is_empty() {
+        settings.output_profile.subject_prefixes()
+    } else {
+        settings.subject_prefixes.clone()
+    };
+    let (steering, subject) = subjectprefix::parse(&prefixes, subject);
+
+    // Front matter directives at the top of the body take precedence over the headers
+    let (directives, content) = match body(&mail, settings.prefer_html)? {
+        Some(text) => {
+            let (directives, content) = directives::extract(&text);
+            (directives, Some(content))
+        }
+        None => (Directives::default(), None),
+    };
+    let mut directives = directives;
+    if !trusted {
+        let removed = directives.remove_template_code();
+        if !removed.is_empty() {
+            debug!("Ignoring front matter holding template code: {:?}", removed);
+        }
+    }
+    directives.extend_list("tags", &steering.tags);
+    for (key, value) in steering.front_matter.iter() {
+        directives.set_default(key, directives::scalar(value));
+    }
+
+    let sender: String = directives
+        .get("author")
+        .or_else(|| token.and_then(|t| t.author.clone()))
+        .or_else(|| from.and_then(|info| info.display_name))
+        .unwrap_or_else(|| String::from("Someone"));
+    let keep_signature = allowlist::matches(&settings.keep_signature_senders, &address);
+    let content: Option<String> = content
+        .map(|text| {
+            if keep_signature {
+                text
+            } else {
+                signatureblock::remove(text)
+            }
+        })
+        .map(|text| cleanup::apply(&settings.cleanup, &settings.cleanup_patterns, text));
+    let date: DateTime<Utc> = match directives.date()? {
+        Some(date) => date,
+        None => date(&mail)?.unwrap_or_else(Utc::now),
+    };
 
     // The blog post title will be the subject line, and if that's missing use the body text
-    let title = &subject
+    let title = &directives
+        .get("title")
+        .or(subject)
         .filter(|str| !str.is_empty())
         .or_else(|| content.clone())
         .unwrap_or_else(|| String::from("Untitled"));
 
     let slug = slug::slugify(title);
-
-    let conventions = FileConventions::new(
-        out_dir,
-        &settings.media_path,
-        &settings.posts_path,
-        &date,
-        &slug,
-    )?;
+    let posts_path = steering
+        .posts_path
+        .as_deref()
+        .unwrap_or(&settings.posts_path);
+    let conventions = unique_conventions(settings, out_dir, posts_path, state, &date, &slug)?;
 
     let attachments = attachments(&conventions, settings.width, &mail)?;
 
+    let permalink = directives
+        .get("permalink")
+        .unwrap_or_else(|| conventions.permalink());
+    let front_matter = directives.without(&["title", "author", "date", "permalink"]);
+
+    // Unless the sender is trusted, the post must not run code on the site or its build
+    let content = if trusted {
+        content
+    } else {
+        content.map(|text| {
+            let text = sanitise::links(&sanitise::html(&text));
+            sanitise::template_syntax(settings.output_profile, &text)
+        })
+    };
+
     let info = PostInfo::new(
         title.to_string(),
         sender,
         content,
         date,
-        conventions.permalink(),
+        permalink,
         attachments,
         conventions.post_path(),
         conventions.post_filename(),
+        front_matter,
+        message_id(&mail),
     );
 
     debug!("{:#?}", &info);
@@ -98,6 +168,59 @@ pub fn extract(settings: &Settings, out_dir: &Path, mail: ParsedMail) -> Result<
     Ok(info)
 }
 
+/// What the state store knows an email by: its Message-ID,
+/// or for an email without one, a digest of the whole message.
+pub fn state_key(mail: &ParsedMail) -> String {
+    message_id(mail).unwrap_or_else(|| {
+        let hash = digest(&SHA256, mail.raw_bytes);
+        let hex: String = hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256:{}", hex)
+    })
+}
+
+pub fn message_id(mail: &ParsedMail) -> Option<String> {
+    mail.headers
+        .get_first_value("Message-ID")
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+// Posts with the same slug would share a permalink, so number any repeats: slug, slug-2, slug-3...
+fn unique_conventions(
+    settings: &Settings,
+    out_dir: &Path,
+    posts_path: &str,
+    state: &StateStore,
+    date: &DateTime<Utc>,
+    slug: &str,
+) -> Result<FileConventions, Mishap> {
+    // The posts path may come from the subject line, so might not exist yet
+    std::fs::create_dir_all(out_dir.join(posts_path))?;
+
+    let mut candidate = slug.to_string();
+    let mut count = 1;
+    loop {
+        let conventions = FileConventions::new(
+            out_dir,
+            // Checked at startup for the profiles that use it
+            settings.media_path.as_deref().unwrap_or_default(),
+            posts_path,
+            settings.output_profile,
+            date,
+            &candidate,
+        )?;
+
+        let taken =
+            state.permalink_taken(&conventions.permalink()) || conventions.post_filename().exists();
+        if !taken {
+            return Ok(conventions);
+        }
+
+        count += 1;
+        candidate = format!("{}-{}", slug, count);
+    }
+}
+
 fn date(mail: &ParsedMail) -> Result<Option<DateTime<Utc>>, Mishap> {
     match mail.headers.get_first_value("Date") {
         None => Ok(None),
@@ -114,37 +237,98 @@ fn date(mail: &ParsedMail) -> Result<Option<DateTime<Utc>>, Mishap> {
     }
 }
 
-fn sender(mail: &ParsedMail) -> Result<Option<String>, MailParseError> {
+fn sender(mail: &ParsedMail) -> Result<Option<SingleInfo>, MailParseError> {
     let sender_text: Option<String> = mail.headers.get_first_value("From");
     match sender_text {
         None => Ok(None),
         Some(str) => match addrparse(&str) {
             Err(err) => Err(err),
             Ok(addrs) if addrs.is_empty() => Ok(None),
-            Ok(addrs) => Ok(addrs
-                .extract_single_info()
-                .and_then(|info| info.display_name)),
+            Ok(addrs) => Ok(addrs.extract_single_info()),
         },
     }
 }
 
-fn body(mail: &ParsedMail) -> Result<Option<String>, MailParseError> {
-    if mail.ctype.mimetype == "text/plain" {
-        mail.get_body().map(Some)
-    } else if mail.subparts.is_empty() {
-        Ok(None)
-    } else {
-        let parts: Result<Vec<Option<String>>, MailParseError> =
-            mail.subparts.iter().map(body).collect();
+/// Recipient addresses. Addresses that do not parse are skipped:
+/// if a whole header does not parse, each comma-separated part is tried on its own.
+fn recipients(mail: &ParsedMail) -> Vec<String> {
+    let headers = ["To", "Cc", "Delivered-To", "X-Original-To"];
+    let mut addresses = Vec::new();
+    for header in headers {
+        for value in mail.headers.get_all_values(header) {
+            let lists = match addrparse(&value) {
+                Ok(list) => vec![list],
+                Err(_) => value
+                    .split(',')
+                    .filter_map(|part| addrparse(part).ok())
+                    .collect(),
+            };
+            for addr in lists.iter().flat_map(|list| list.iter()) {
+                match addr {
+                    MailAddr::Single(info) => addresses.push(info.addr.clone()),
+                    MailAddr::Group(group) => {
+                        addresses.extend(group.addrs.iter().map(|info| info.addr.clone()))
+                    }
+                }
+            }
+        }
+    }
+    addresses
+}
 
-        let valid_parts: Result<Vec<String>, MailParseError> =
-            parts.map(|os| os.into_iter().flatten().collect());
+/// The text of the email: the plain text part, or the HTML part converted to Markdown.
+fn body(mail: &ParsedMail, prefer_html: bool) -> Result<Option<String>, MailParseError> {
+    match body_part(mail, prefer_html)? {
+        Some(part) if part.ctype.mimetype == "text/plain" => plain_text(part).map(Some),
+        Some(part) => part.get_body().map(|text| Some(html::to_markdown(&text))),
+        None => Ok(None),
+    }
+}
 
-        match valid_parts {
-            Ok(vec) if vec.is_empty() => Ok(None),
-            Err(err) => Err(err),
-            Ok(vec) => Ok(Some(vec[0].clone())),
+/// The part `body` uses: the plain text part, unless there is no plain part,
+/// HTML is preferred, or the HTML places inline (`cid:`) images.
+fn body_part<'a>(
+    mail: &'a ParsedMail<'a>,
+    prefer_html: bool,
+) -> Result<Option<&'a ParsedMail<'a>>, MailParseError> {
+    let plain = first_part(mail, "text/plain");
+    let html = first_part(mail, "text/html");
+
+    let part = match (plain, html) {
+        (Some(plain), Some(html)) if !prefer_html && !places_images(&html.get_body()?) => {
+            Some(plain)
         }
+        (plain, None) => plain,
+        (_, html) => html,
+    };
+    Ok(part)
+}
+
+/// Whether HTML refers to images attached to the email.
+fn places_images(html: &str) -> bool {
+    let cid = Regex::new(r#"(?i)src\s*=\s*["']?cid:"#).unwrap();
+    cid.is_match(html)
+}
+
+/// A text/plain part, unwrapping soft line breaks if it is `format=flowed`.
+fn plain_text(part: &ParsedMail) -> Result<String, MailParseError> {
+    let param = |name: &str| part.ctype.params.get(name).map(|v| v.to_lowercase());
+    let text = part.get_body()?;
+    if param("format").as_deref() == Some("flowed") {
+        let delsp = param("delsp").as_deref() == Some("yes");
+        Ok(flowed::decode(&text, delsp))
+    } else {
+        Ok(text)
+    }
+}
+
+fn first_part<'a>(mail: &'a ParsedMail<'a>, mimetype: &str) -> Option<&'a ParsedMail<'a>> {
+    if mail.ctype.mimetype == mimetype {
+        Some(mail)
+    } else {
+        mail.subparts
+            .iter()
+            .find_map(|part| first_part(part, mimetype))
     }
 }
 
@@ -182,6 +366,7 @@ fn attachments(
         let thumbnail = Thumbnail {
             file: thumb_filename,
             relative_path: conventions.attachment_thumb_url(count),
+            url: conventions.attachment_thumb_link(count),
             width,
             height,
         };
@@ -189,14 +374,29 @@ fn attachments(
         images.push(Image {
             file: filename,
             relative_path: conventions.attachment_url(count),
+            url: conventions.attachment_link(count),
             thumbnail,
-            mimetype: mail.ctype.mimetype.clone(), //TODO: should be part.ctype
+            mimetype: part.ctype.mimetype.clone(),
+            content_id: content_id(part),
         });
     }
 
     Ok(images)
 }
 
+/// The Content-ID of a part, as referred to by `cid:` URLs.
+fn content_id(part: &ParsedMail) -> Option<String> {
+    part.headers
+        .get_first_value("Content-ID")
+        .map(|id| {
+            id.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .filter(|id| !id.is_empty())
+}
+
 fn save_raw_body(filename: &Path, bytes: Vec<u8>) -> Result<File, Mishap> {
     let mut file = File::create(filename)?;
     file.write_all(bytes.as_slice())?;
@@ -219,3 +419,127 @@ fn describe_child(prefix: &str, mail: &ParsedMail) {
         describe_child(&indent, child);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(settings: &Settings, raw: &[u8]) -> PostInfo {
+        let dir = tempfile::tempdir().unwrap();
+        extract(
+            settings,
+            dir.path(),
+            &StateStore::default(),
+            parse(raw).unwrap(),
+        )
+        .unwrap()
+    }
+
+    const HOSTILE_HTML: &[u8] = b"From: Mallory <m@example.com>\r\nAuthentication-Results: mx.example.net; dmarc=pass header.from=example.com\r\nSubject: Hi\r\nDate: Wed, 15 Jan 2020 21:26:00 +0000\r\nContent-Type: text/html\r\n\r\n<p>&lt;script&gt;alert(1)&lt;/script&gt; <a href=\"javascript:alert(2)\">click</a> {{ site.url }} <sup onclick=\"x()\">1</sup></p>\r\n";
+
+    #[test]
+    fn test_html_from_untrusted_sender_sanitised() {
+        let content = post(&Settings::for_test(&[]), HOSTILE_HTML)
+            .content
+            .unwrap();
+        assert_eq!(
+            "&lt;script\\>alert(1)&lt;/script\\> click {% raw %}{{{% endraw %} site.url }} &lt;sup onclick=\"x()\">1&lt;/sup>",
+            content
+        );
+    }
+
+    #[test]
+    fn test_html_from_trusted_sender_kept() {
+        let settings = Settings::for_test(&[
+            "--trusted-sender=example.com",
+            "--trusted-authserv-id=mx.example.net",
+        ]);
+        let content = post(&settings, HOSTILE_HTML).content.unwrap();
+        assert!(content.contains("<sup onclick=\"x()\">1</sup>"));
+        assert!(content.contains("{{ site.url }}"));
+    }
+
+    #[test]
+    fn test_unauthenticated_trusted_sender_sanitised() {
+        let settings = Settings::for_test(&["--trusted-sender=example.com"]);
+        let content = post(&settings, HOSTILE_HTML).content.unwrap();
+        assert!(!content.contains("{{ site.url }}"));
+    }
+
+    #[test]
+    fn test_unparsable_recipients_skipped() {
+        let raw = b"To: blog+s3cret@example.org, <>\r\nCc: Smith, J <j@example.org>\r\nSubject: Hi\r\n\r\nHello\r\n";
+        let mail = parse(raw).unwrap();
+        assert_eq!(
+            vec!["blog+s3cret@example.org", "j@example.org"],
+            recipients(&mail)
+        );
+    }
+
+    #[test]
+    fn test_state_key() {
+        let with_id =
+            parse(b"Message-ID: <1@example.org>\r\nSubject: Hi\r\n\r\nHello\r\n").unwrap();
+        assert_eq!("<1@example.org>", state_key(&with_id));
+
+        let without_id = b"Subject: Hi\r\n\r\nHello\r\n";
+        let key = state_key(&parse(without_id).unwrap());
+        assert!(key.starts_with("sha256:"));
+        assert_eq!(key, state_key(&parse(without_id).unwrap()));
+        assert_ne!(
+            key,
+            state_key(&parse(b"Subject: Hi\r\n\r\nBye\r\n").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_8bit_latin1_body() {
+        let raw = b"Subject: Caf\xe9\r\nContent-Type: text/plain; charset=iso-8859-1\r\nContent-Transfer-Encoding: 8bit\r\n\r\nCaf\xe9 au lait\r\n";
+        let mail = parse(raw).unwrap();
+        assert_eq!(
+            Some("Café au lait\r\n".to_string()),
+            body(&mail, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_html_only_body() {
+        let raw = b"Subject: Hi\r\nContent-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>Hello <em>there</em></p>\r\n--b--\r\n";
+        let mail = parse(raw).unwrap();
+        assert_eq!(
+            Some("Hello *there*".to_string()),
+            body(&mail, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_prefer_html_body() {
+        let raw = b"Subject: Hi\r\nContent-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nHello there\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>Hello <em>there</em></p>\r\n--b--\r\n";
+        let mail = parse(raw).unwrap();
+        assert_eq!(Some("Hello there".to_string()), body(&mail, false).unwrap());
+        assert_eq!(
+            Some("Hello *there*".to_string()),
+            body(&mail, true).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_html_with_inline_images_preferred() {
+        let raw = b"Subject: Hi\r\nContent-Type: multipart/alternative; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nBefore\r\n\r\nAfter\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>Before</p><img src=\"cid:photo\" alt=\"\"><p>After</p>\r\n--b--\r\n";
+        let mail = parse(raw).unwrap();
+        assert_eq!(
+            Some("Before\n\n![](cid:photo)\n\nAfter".to_string()),
+            body(&mail, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_flowed_body() {
+        let raw = b"Subject: Hi\r\nContent-Type: text/plain; charset=utf-8; format=flowed; delsp=yes\r\n\r\nA soft  \r\nbreak\r\n";
+        let mail = parse(raw).unwrap();
+        assert_eq!(
+            Some("A soft break\n".to_string()),
+            body(&mail, false).unwrap()
+        );
+    }
+}
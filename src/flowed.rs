@@ -0,0 +1,79 @@
+/// Unwrap a `format=flowed` plain text body.
+/// Lines ending in a space are soft breaks and are joined to the next line at the same quote depth.
+/// With `delsp=yes` the trailing space was added by the sender's wrapping, so it is removed.
+/// https://www.rfc-editor.org/rfc/rfc3676
+pub fn decode(text: &str, delsp: bool) -> String {
+    let mut decoded = String::new();
+    let mut paragraph: Option<(usize, String)> = None;
+
+    for line in text.lines() {
+        let depth = line.chars().take_while(|&c| c == '>').count();
+        let content = &line[depth..];
+        // Space-stuffing protects lines that start with a space, `>` or `From `
+        let content = content.strip_prefix(' ').unwrap_or(content);
+        // The signature separator is never flowed
+        let flowed = content.ends_with(' ') && content != "-- ";
+
+        if paragraph.as_ref().is_some_and(|(d, _)| *d != depth) {
+            finish(&mut decoded, paragraph.take());
+        }
+
+        let content = match content.strip_suffix(' ') {
+            Some(without_space) if flowed && delsp => without_space,
+            _ => content,
+        };
+
+        let (_, text) = paragraph.get_or_insert_with(|| (depth, String::new()));
+        text.push_str(content);
+
+        if !flowed {
+            finish(&mut decoded, paragraph.take());
+        }
+    }
+
+    finish(&mut decoded, paragraph);
+    decoded
+}
+
+fn finish(decoded: &mut String, paragraph: Option<(usize, String)>) {
+    if let Some((depth, text)) = paragraph {
+        if depth > 0 {
+            decoded.push_str(&">".repeat(depth));
+            decoded.push(' ');
+        }
+        decoded.push_str(&text);
+        decoded.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_breaks_joined() {
+        let text = "This is a long \r\nparagraph.\r\n\r\nSecond one.\r\n";
+        assert_eq!(
+            "This is a long paragraph.\n\nSecond one.\n",
+            decode(text, false)
+        );
+    }
+
+    #[test]
+    fn test_delsp() {
+        let text = "Donau\r\ndampf \r\nschiff\r\n";
+        assert_eq!("Donau\ndampfschiff\n", decode(text, true));
+    }
+
+    #[test]
+    fn test_space_stuffing_and_quotes() {
+        let text = "> Quoted \r\n> text\r\n From here\r\n  indented\r\n";
+        assert_eq!("> Quoted text\nFrom here\n indented\n", decode(text, false));
+    }
+
+    #[test]
+    fn test_signature_not_flowed() {
+        let text = "Bye \r\nnow\r\n-- \r\nAlice\r\n";
+        assert_eq!("Bye now\n-- \nAlice\n", decode(text, false));
+    }
+}
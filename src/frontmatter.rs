@@ -0,0 +1,234 @@
+use serde::Serialize;
+use serde_yaml_ng::{Mapping, Value};
+
+use super::blog::PostInfo;
+use super::settings::OutputProfile;
+
+/// Keys Zola accepts at the top level; anything else goes in `[extra]`.
+const ZOLA_TOP_LEVEL: [&str; 5] = ["description", "draft", "weight", "aliases", "template"];
+const ZOLA_TAXONOMIES: [&str; 2] = ["tags", "categories"];
+
+/// The front matter for a post, arranged the way the output profile's generator expects.
+/// Fields from the email (such as tags) follow the standard ones.
+pub fn for_profile(post: &PostInfo, profile: OutputProfile) -> Mapping {
+    let rfc3339 = post.date.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let mut front_matter = Mapping::new();
+    front_matter.insert("title".into(), post.title.as_str().into());
+
+    match profile {
+        OutputProfile::Jekyll => {
+            front_matter.insert("author".into(), post.author.as_str().into());
+            let date = post.date.format("%Y-%m-%d %H:%M").to_string();
+            front_matter.insert("date".into(), date.into());
+            front_matter.insert("permalink".into(), post.permalink.as_str().into());
+            front_matter.insert("layout".into(), field(post, "layout", "post".into()));
+            front_matter.insert("comments".into(), field(post, "comments", true.into()));
+            extend(&mut front_matter, post, |key| {
+                key != "layout" && key != "comments"
+            });
+        }
+        OutputProfile::Eleventy => {
+            front_matter.insert("author".into(), post.author.as_str().into());
+            front_matter.insert("date".into(), rfc3339.into());
+            front_matter.insert("permalink".into(), post.permalink.as_str().into());
+            front_matter.insert("layout".into(), field(post, "layout", "post".into()));
+            extend(&mut front_matter, post, |key| key != "layout");
+        }
+        OutputProfile::Hugo => {
+            front_matter.insert("author".into(), post.author.as_str().into());
+            front_matter.insert("date".into(), rfc3339.into());
+            front_matter.insert("url".into(), post.permalink.as_str().into());
+            extend(&mut front_matter, post, |_| true);
+        }
+        OutputProfile::Zola => {
+            front_matter.insert("date".into(), rfc3339.into());
+            front_matter.insert("path".into(), post.permalink.as_str().into());
+            extend(&mut front_matter, post, |key| ZOLA_TOP_LEVEL.contains(&key));
+
+            let mut taxonomies = Mapping::new();
+            extend(&mut taxonomies, post, |key| ZOLA_TAXONOMIES.contains(&key));
+            if !taxonomies.is_empty() {
+                front_matter.insert("taxonomies".into(), taxonomies.into());
+            }
+
+            let mut extra = Mapping::new();
+            extra.insert("author".into(), post.author.as_str().into());
+            extend(&mut extra, post, |key| {
+                !ZOLA_TOP_LEVEL.contains(&key) && !ZOLA_TAXONOMIES.contains(&key)
+            });
+            front_matter.insert("extra".into(), extra.into());
+        }
+    }
+
+    front_matter
+}
+
+fn field(post: &PostInfo, key: &str, default: Value) -> Value {
+    post.front_matter
+        .iter()
+        .find(|(k, _)| k == key)
+        .map_or(default, |(_, value)| value.clone())
+}
+
+fn extend<F: Fn(&str) -> bool>(front_matter: &mut Mapping, post: &PostInfo, include: F) {
+    for (key, value) in post.front_matter.iter() {
+        if include(key) {
+            front_matter.insert(key.as_str().into(), value.clone());
+        }
+    }
+}
+
+pub fn to_yaml<T: Serialize>(front_matter: &T) -> Result<String, String> {
+    serde_yaml_ng::to_string(front_matter).map_err(|err| err.to_string())
+}
+
+/// TOML, with the date as a TOML date-time rather than a string.
+/// TOML has no null, so empty fields (such as `summary:` with no value) are left out.
+pub fn to_toml<T: Serialize>(front_matter: &T) -> Result<String, String> {
+    let value = serde_yaml_ng::to_value(front_matter).map_err(|err| err.to_string())?;
+    let mut table = toml::Table::try_from(without_nulls(value)).map_err(|err| err.to_string())?;
+    let date = match table.get("date") {
+        Some(toml::Value::String(date)) => date.parse::<toml::value::Datetime>().ok(),
+        _ => None,
+    };
+    if let Some(date) = date {
+        table.insert("date".to_string(), toml::Value::Datetime(date));
+    }
+    toml::to_string(&table).map_err(|err| err.to_string())
+}
+
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => mapping
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect::<Mapping>()
+            .into(),
+        Value::Sequence(items) => items
+            .into_iter()
+            .filter(|item| !item.is_null())
+            .map(without_nulls)
+            .collect::<Vec<Value>>()
+            .into(),
+        Value::Tagged(tagged) => without_nulls(tagged.value),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    fn awkward_post() -> PostInfo {
+        PostInfo::new(
+            "Lunch: the sequel # two\nwith a second line".to_string(),
+            "Alice: \"The\" Cook #1".to_string(),
+            None,
+            Utc.with_ymd_and_hms(2020, 1, 15, 21, 26, 0).unwrap(),
+            "/lunch-[2]?x=1&y='z'".to_string(),
+            Vec::new(),
+            "_posts/2020-01-15-lunch.md".to_string(),
+            PathBuf::new(),
+            vec![
+                (
+                    "tags".to_string(),
+                    Value::from(vec!["travel", "yes", "null"]),
+                ),
+                ("layout".to_string(), Value::from("note")),
+            ],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let post = awkward_post();
+        let yaml = to_yaml(&for_profile(&post, OutputProfile::Jekyll)).unwrap();
+        let parsed: Mapping = serde_yaml_ng::from_str(&yaml).unwrap();
+
+        let text = |key: &str| parsed.get(key).and_then(Value::as_str);
+        assert_eq!(Some(post.title.as_str()), text("title"));
+        assert_eq!(Some(post.author.as_str()), text("author"));
+        assert_eq!(Some("2020-01-15 21:26"), text("date"));
+        assert_eq!(Some(post.permalink.as_str()), text("permalink"));
+        assert_eq!(Some("note"), text("layout"));
+        assert_eq!(Some(&Value::Bool(true)), parsed.get("comments"));
+        assert_eq!(Some(&post.front_matter[0].1), parsed.get("tags"));
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let post = awkward_post();
+        let toml = to_toml(&for_profile(&post, OutputProfile::Hugo)).unwrap();
+        let parsed: toml::Table = toml::from_str(&toml).unwrap();
+
+        let text = |key: &str| parsed.get(key).and_then(toml::Value::as_str);
+        assert_eq!(Some(post.title.as_str()), text("title"));
+        assert_eq!(Some(post.author.as_str()), text("author"));
+        assert_eq!(Some(post.permalink.as_str()), text("url"));
+        assert_eq!(
+            Some("2020-01-15T21:26:00Z".to_string()),
+            parsed
+                .get("date")
+                .and_then(toml::Value::as_datetime)
+                .map(|date| date.to_string())
+        );
+        let tags: Vec<&str> = parsed["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(toml::Value::as_str)
+            .collect();
+        assert_eq!(vec!["travel", "yes", "null"], tags);
+    }
+
+    #[test]
+    fn test_zola_tables() {
+        let post = awkward_post();
+        let toml = to_toml(&for_profile(&post, OutputProfile::Zola)).unwrap();
+        let parsed: toml::Table = toml::from_str(&toml).unwrap();
+
+        assert_eq!(Some(post.permalink.as_str()), parsed["path"].as_str());
+        assert!(parsed["taxonomies"]["tags"].is_array());
+        assert_eq!(
+            Some(post.author.as_str()),
+            parsed["extra"]["author"].as_str()
+        );
+        assert_eq!(Some("note"), parsed["extra"]["layout"].as_str());
+    }
+
+    #[test]
+    fn test_toml_without_nulls() {
+        let mut post = awkward_post();
+        post.front_matter = vec![
+            ("summary".to_string(), Value::Null),
+            (
+                "tags".to_string(),
+                serde_yaml_ng::from_str("[travel, null]").unwrap(),
+            ),
+            (
+                "weight".to_string(),
+                serde_yaml_ng::from_str("{a: ~, b: 2}").unwrap(),
+            ),
+        ];
+        for profile in [OutputProfile::Hugo, OutputProfile::Zola] {
+            let toml = to_toml(&for_profile(&post, profile)).unwrap();
+            let parsed: toml::Table = toml::from_str(&toml).unwrap();
+            assert!(!toml.contains("summary"));
+            assert!(toml.contains(r#"tags = ["travel"]"#));
+            assert_eq!(Some(post.title.as_str()), parsed["title"].as_str());
+        }
+    }
+
+    #[test]
+    fn test_zola_template_at_top_level() {
+        let mut post = awkward_post();
+        post.front_matter = vec![("template".to_string(), Value::from("note.html"))];
+        let toml = to_toml(&for_profile(&post, OutputProfile::Zola)).unwrap();
+        let parsed: toml::Table = toml::from_str(&toml).unwrap();
+        assert_eq!(Some("note.html"), parsed["template"].as_str());
+    }
+}
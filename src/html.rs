@@ -0,0 +1,166 @@
+use html2md::anchors::AnchorHandler;
+use html2md::common::get_tag_attr;
+use html2md::{Handle, StructuredPrinter, TagHandler, TagHandlerFactory};
+
+use std::collections::HashMap;
+
+/// Convert the HTML part of an email to Markdown.
+/// Paragraphs, links, emphasis, lists and quotes are kept; scripts, styles, embedded content,
+/// forms, script links and tracking pixels are not.
+pub fn to_markdown(html: &str) -> String {
+    let mut handlers: HashMap<String, Box<dyn TagHandlerFactory>> = HashMap::new();
+    for tag in [
+        "head", "title", "script", "style", "noscript", "iframe", "object", "embed", "form",
+    ] {
+        handlers.insert(tag.to_string(), Box::new(Dropped));
+    }
+    handlers.insert("img".to_string(), Box::new(Image));
+    handlers.insert("a".to_string(), Box::new(Link));
+    html2md::parse_html_custom(html, &handlers)
+}
+
+/// Elements which are removed along with everything inside them.
+struct Dropped;
+
+impl TagHandlerFactory for Dropped {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(Dropped)
+    }
+}
+
+impl TagHandler for Dropped {
+    fn handle(&mut self, _tag: &Handle, _printer: &mut StructuredPrinter) {}
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+
+    fn skip_descendants(&self) -> bool {
+        true
+    }
+}
+
+/// Links, unless they run script, in which case only the text is kept.
+struct Link;
+
+impl TagHandlerFactory for Link {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(LinkHandler(None))
+    }
+}
+
+struct LinkHandler(Option<AnchorHandler>);
+
+impl TagHandler for LinkHandler {
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        if !get_tag_attr(tag, "href").is_some_and(|href| is_script_url(&href)) {
+            let mut handler = AnchorHandler::default();
+            handler.handle(tag, printer);
+            self.0 = Some(handler);
+        }
+    }
+
+    fn after_handle(&mut self, printer: &mut StructuredPrinter) {
+        if let Some(handler) = self.0.as_mut() {
+            handler.after_handle(printer);
+        }
+    }
+}
+
+/// A `javascript:`, `vbscript:` or `data:` URL.
+/// Browsers ignore whitespace and case in the scheme, so this does too.
+pub fn is_script_url(url: &str) -> bool {
+    let scheme: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .take_while(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase();
+    url.contains(':') && ["javascript", "vbscript", "data"].contains(&scheme.as_str())
+}
+
+/// Images, unless they are tracking pixels.
+struct Image;
+
+impl TagHandlerFactory for Image {
+    fn instantiate(&self) -> Box<dyn TagHandler> {
+        Box::new(ImageHandler)
+    }
+}
+
+struct ImageHandler;
+
+impl TagHandler for ImageHandler {
+    /// Always a Markdown image, never an HTML `<img>` with whatever attributes the sender gave it.
+    fn handle(&mut self, tag: &Handle, printer: &mut StructuredPrinter) {
+        let src = get_tag_attr(tag, "src").unwrap_or_default();
+        if src.is_empty() || is_script_url(&src) || is_tracking_pixel(tag) {
+            return;
+        }
+        let alt = get_tag_attr(tag, "alt").unwrap_or_default();
+        printer.append_str(&format!(
+            "![{}]({})",
+            alt.replace(['[', ']'], ""),
+            src.trim().replace(' ', "%20")
+        ));
+    }
+
+    fn after_handle(&mut self, _printer: &mut StructuredPrinter) {}
+}
+
+/// Tiny or hidden images, used to report when an email is opened.
+fn is_tracking_pixel(tag: &Handle) -> bool {
+    let tiny = |value: Option<String>| {
+        value.is_some_and(|v| {
+            v.trim()
+                .trim_end_matches("px")
+                .parse::<f32>()
+                .is_ok_and(|n| n <= 1.0)
+        })
+    };
+
+    let style = get_tag_attr(tag, "style")
+        .unwrap_or_default()
+        .to_lowercase()
+        .replace(' ', "");
+    let hidden = style.contains("display:none")
+        || style.contains("visibility:hidden")
+        || style.contains("width:1px")
+        || style.contains("height:1px")
+        || style.contains("width:0")
+        || style.contains("height:0");
+
+    hidden || tiny(get_tag_attr(tag, "width")) || tiny(get_tag_attr(tag, "height"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatting_kept() {
+        let html = r#"<p>Hello <b>bold</b> and <i>it</i> <a href="https://example.org">link</a>.</p><ul><li>one</li><li>two</li></ul><blockquote>quoted</blockquote>"#;
+        let markdown = to_markdown(html);
+        assert!(markdown.contains("Hello **bold** and *it* [link](https://example.org)."));
+        assert!(markdown.contains("* one\n* two"));
+        assert!(markdown.contains("> quoted"));
+    }
+
+    #[test]
+    fn test_scripts_styles_and_pixels_removed() {
+        let html = r#"<html><head><title>Newsletter</title><style>p { color: red }</style></head><body><script>track()</script><p>Hello</p><img src="https://t.example/open.gif" width="1" height="1"><img src="https://t.example/o.png" style="display: none"><img src="cid:photo" alt="Photo" width="640" onerror="alert(1)"></body></html>"#;
+        assert_eq!("Hello\n\n![Photo](cid:photo)", to_markdown(html));
+    }
+
+    #[test]
+    fn test_embedded_content_and_script_links_removed() {
+        let html = r#"<div><iframe src="https://evil.example"></iframe><object data="x.swf"></object><embed src="x.swf"><form action="https://evil.example"><input name="q"></form><p><a href="JavaScript:alert(1)">click</a> <a href=" data:text/html,x">data</a> <a href="https://example.org">safe</a></p></div>"#;
+        assert_eq!("click data [safe](https://example.org)", to_markdown(html));
+    }
+
+    #[test]
+    fn test_script_url() {
+        assert!(is_script_url("javascript:alert(1)"));
+        assert!(is_script_url(" Java\tScript:alert(1)"));
+        assert!(!is_script_url("https://example.org/javascript:"));
+        assert!(!is_script_url("cid:photo"));
+    }
+}
@@ -3,21 +3,24 @@ use std::path::Path;
 use std::process::Command;
 
 pub fn thumbnail(source: &Path, target: &Path, width: u16) -> Result<(u16, u16), Error> {
-    let _convert_status = Command::new("convert")
+    let convert_status = Command::new("convert")
         .arg(source)
         .arg("-resize")
         .arg(width.to_string())
         .arg("-auto-orient")
         .arg(target)
-        .status()
-        .expect("failed to execute convert process");
+        .status()?;
+
+    if !convert_status.success() {
+        let msg = format!("convert failed on {:?}: {}", source, convert_status);
+        return Err(Error::other(msg));
+    }
 
     let identify_output = Command::new("identify")
         .arg("-format")
         .arg("%wx%h")
         .arg(target)
-        .output()
-        .expect("failed to identify thumbnail");
+        .output()?;
 
     let output_text = String::from_utf8_lossy(&identify_output.stdout);
 
@@ -0,0 +1,48 @@
+use std::fmt;
+use std::path::Path;
+
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use super::source::{self, Outcome};
+use super::state::StateStore;
+
+/// What happened to each message in an archive.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub created: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Created: {}, skipped: {}, failed: {}",
+            self.created, self.skipped, self.failed
+        )
+    }
+}
+
+/// Publish every message in a mail archive (an mbox, Maildir or .eml files) as a post dated by its `Date` header.
+/// Messages whose Message-ID is already in the state store are skipped, so an import can be re-run.
+pub fn run(
+    refresh: &str,
+    settings: &Settings,
+    out_dir: &Path,
+    archive: &Path,
+) -> Result<Summary, Mishap> {
+    let mut state = StateStore::open(settings.state_file.as_deref())?;
+    let mut archive = source::open_local(archive, settings.input_format);
+    let outcomes = super::process(refresh, settings, out_dir, &mut state, archive.as_mut())?;
+
+    let mut summary = Summary::default();
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Published => summary.created += 1,
+            Outcome::Skipped => summary.skipped += 1,
+            Outcome::Failed => summary.failed += 1,
+        }
+    }
+    Ok(summary)
+}
@@ -0,0 +1,223 @@
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::mishaps::Mishap;
+use super::settings::InputFormat;
+use super::source::{Fetched, Outcome, Source};
+
+/// Emails stored in local files, in the given format.
+pub fn open(path: &Path, format: InputFormat) -> Box<dyn Source> {
+    match format {
+        InputFormat::Eml => Box::new(EmlFiles {
+            dir: None,
+            files: vec![path.to_path_buf()],
+        }),
+        InputFormat::EmlDir => Box::new(EmlFiles {
+            dir: Some(path.to_path_buf()),
+            files: Vec::new(),
+        }),
+        InputFormat::Mbox => Box::new(Mbox {
+            path: path.to_path_buf(),
+        }),
+        InputFormat::Maildir | InputFormat::Auto => Box::new(Maildir {
+            dir: path.to_path_buf(),
+            files: Vec::new(),
+        }),
+    }
+}
+
+/// One message per file, either a single file or every .eml file in a directory.
+/// The files are left as they are after processing.
+struct EmlFiles {
+    dir: Option<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl Source for EmlFiles {
+    fn fetch(&mut self) -> Result<Vec<Fetched>, Mishap> {
+        if let Some(dir) = &self.dir {
+            self.files = files_in(dir, |file| {
+                file.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+            })?;
+        }
+
+        let mut fetched = Vec::new();
+        for (id, file) in self.files.iter().enumerate() {
+            fetched.push(Fetched {
+                id: id as u32,
+                mime: fs::read(file).map_err(Mishap::File),
+            });
+        }
+        Ok(fetched)
+    }
+
+    fn commit(&mut self, _outcomes: Vec<(u32, Outcome)>) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// Many messages in one file, each starting with a `From ` line. The file is left as it is.
+struct Mbox {
+    path: PathBuf,
+}
+
+impl Source for Mbox {
+    fn fetch(&mut self) -> Result<Vec<Fetched>, Mishap> {
+        let bytes = fs::read(&self.path)?;
+        let fetched = split_mbox(&bytes)
+            .into_iter()
+            .enumerate()
+            .map(|(id, mime)| Fetched {
+                id: id as u32,
+                mime: Ok(mime),
+            })
+            .collect();
+        Ok(fetched)
+    }
+
+    fn commit(&mut self, _outcomes: Vec<(u32, Outcome)>) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// A Maildir (e.g., as delivered by Postfix). Unread messages in `new` and `cur` are processed,
+/// and published ones are moved to `cur` and marked as seen.
+struct Maildir {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl Source for Maildir {
+    fn fetch(&mut self) -> Result<Vec<Fetched>, Mishap> {
+        let unseen = |file: &Path| !is_seen(file);
+        let mut files = files_in(&self.dir.join("new"), unseen)?;
+        files.extend(files_in(&self.dir.join("cur"), unseen)?);
+
+        // Maildir file names start with the delivery time
+        files.sort_by_key(|file| file.file_name().map(|name| name.to_os_string()));
+
+        let mut fetched = Vec::new();
+        for (id, file) in files.iter().enumerate() {
+            fetched.push(Fetched {
+                id: id as u32,
+                mime: fs::read(file).map_err(Mishap::File),
+            });
+        }
+        self.files = files;
+        Ok(fetched)
+    }
+
+    fn commit(&mut self, outcomes: Vec<(u32, Outcome)>) -> Result<(), Mishap> {
+        for (id, outcome) in outcomes {
+            let file = &self.files[id as usize];
+            if outcome != Outcome::Failed {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                let target = self.dir.join("cur").join(seen_name(&name));
+                debug!("Marking {:?} as seen", target);
+                fs::rename(file, target)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The regular files in a directory that match the filter, sorted by name.
+fn files_in<F>(dir: &Path, filter: F) -> Result<Vec<PathBuf>, Mishap>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && filter(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Split an mbox into its messages, undoing the `>From ` quoting of body lines.
+/// Works on bytes, as an archive may hold messages in any mix of encodings.
+fn split_mbox(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+    let mut previous_blank = true;
+
+    for line in bytes.split_inclusive(|&b| b == b'\n') {
+        if previous_blank && line.starts_with(b"From ") {
+            messages.extend(current.take());
+            current = Some(Vec::new());
+        } else if let Some(message) = current.as_mut() {
+            let unquoted = line.iter().position(|&b| b != b'>').map(|i| &line[i..]);
+            let quoted_from = unquoted.is_some_and(|rest| rest.starts_with(b"From "));
+            if line.starts_with(b">") && quoted_from {
+                message.extend_from_slice(&line[1..]);
+            } else {
+                message.extend_from_slice(line);
+            }
+        }
+        previous_blank = line.iter().all(|&b| b == b'\r' || b == b'\n');
+    }
+
+    messages.extend(current);
+    messages
+}
+
+/// Maildir records flags after `:2,` in the file name, with `S` meaning seen.
+fn is_seen(file: &Path) -> bool {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    name.split_once(":2,")
+        .is_some_and(|(_, flags)| flags.contains('S'))
+}
+
+fn seen_name(name: &str) -> String {
+    let (base, flags) = name.split_once(":2,").unwrap_or((name, ""));
+    let mut flags: Vec<char> = flags.chars().chain(['S']).collect();
+    flags.sort();
+    flags.dedup();
+    format!("{}:2,{}", base, flags.into_iter().collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_mbox() {
+        let mbox = "From alice@example.org Mon Jan  6 10:00:00 2020\nSubject: One\n\nHello\n>From the top\n\nFrom bob@example.org Tue Jan  7 10:00:00 2020\nSubject: Two\n\nHi\nFrom here, this is not a separator\n";
+        let messages = split_mbox(mbox.as_bytes());
+        assert_eq!(
+            vec![
+                b"Subject: One\n\nHello\nFrom the top\n\n".to_vec(),
+                b"Subject: Two\n\nHi\nFrom here, this is not a separator\n".to_vec(),
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_split_mbox_8bit() {
+        // "Café" in Latin-1, which is not valid UTF-8
+        let mbox =
+            b"From alice@example.org Mon Jan  6 10:00:00 2020\nSubject: Caf\xe9\n\nCaf\xe9\n";
+        let messages = split_mbox(mbox);
+        assert_eq!(vec![b"Subject: Caf\xe9\n\nCaf\xe9\n".to_vec()], messages);
+    }
+
+    #[test]
+    fn test_maildir_seen_flag() {
+        assert_eq!(
+            "1577836800.M1P2.host:2,S",
+            seen_name("1577836800.M1P2.host")
+        );
+        assert_eq!(
+            "1577836800.M1P2.host:2,FS",
+            seen_name("1577836800.M1P2.host:2,F")
+        );
+        assert!(is_seen(Path::new("cur/1577836800.M1P2.host:2,RS")));
+        assert!(!is_seen(Path::new("new/1577836800.M1P2.host")));
+    }
+}
@@ -0,0 +1,235 @@
+use imap::{Connection, Session};
+use log::debug;
+
+use super::connection;
+use super::mishaps::Mishap;
+use super::oauth::{self, XOAuth2};
+use super::settings::{ImapAuth, Settings};
+use super::source::{Fetched, Outcome, Source};
+
+/// An IMAP mailbox, read over a logged-in session.
+pub struct Imap<'a> {
+    settings: &'a Settings,
+    session: Session<Connection>,
+}
+
+impl<'a> Imap<'a> {
+    pub fn connect(settings: &'a Settings) -> Result<Imap<'a>, Mishap> {
+        let user = settings
+            .user
+            .as_ref()
+            .ok_or(Mishap::MissingSetting("user"))?;
+        let client = connection::connect(settings)?;
+
+        let session = match settings.imap_auth {
+            ImapAuth::Password => {
+                let password = settings
+                    .password
+                    .as_ref()
+                    .ok_or(Mishap::MissingSetting("password"))?;
+                client.login(user, password)
+            }
+            ImapAuth::Xoauth2 => {
+                let xoauth2 = XOAuth2 {
+                    user: user.to_string(),
+                    access_token: oauth::access_token(settings)?,
+                };
+                client.authenticate("XOAUTH2", &xoauth2)
+            }
+        }
+        .map_err(|(err, _client)| err)?;
+
+        let mut imap = Imap { settings, session };
+        imap.ensure_mailboxes()?;
+        Ok(imap)
+    }
+
+    pub fn session(&mut self) -> &mut Session<Connection> {
+        &mut self.session
+    }
+
+    /// Create the archive and failure mailboxes, if they are configured but missing.
+    fn ensure_mailboxes(&mut self) -> Result<(), Mishap> {
+        let configured = [
+            &self.settings.archive_mailbox,
+            &self.settings.failed_mailbox,
+        ];
+        for mailbox in configured.into_iter().flatten() {
+            let existing = self.session.list(None, Some(&quoted(mailbox)))?;
+            if existing.is_empty() {
+                debug!("Creating mailbox: {}", mailbox);
+                self.session.create(mailbox)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Source for Imap<'_> {
+    fn fetch(&mut self) -> Result<Vec<Fetched>, Mishap> {
+        // fetch every message matching the search criteria, along with its full content.
+        // PEEK leaves the \Seen flag alone until we know the post was published.
+
+        self.session.select(&self.settings.mailbox)?;
+
+        let query = search_query(self.settings);
+        debug!("Searching for: {}", query);
+        let uids = self.session.uid_search(&query)?;
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let uid_set = uid_set(uids);
+        let messages = self
+            .session
+            .uid_fetch(&uid_set, "(UID INTERNALDATE BODY.PEEK[])")?;
+
+        // Oldest first, by the date the server received the message
+        let mut messages: Vec<_> = messages.iter().collect();
+        messages.sort_by_key(|m| (m.internal_date(), m.uid));
+
+        let mut fetched = Vec::new();
+        for message in messages {
+            let uid = match message.uid {
+                Some(uid) => uid,
+                None => continue,
+            };
+            // The body will be the raw mime content of the message (including header),
+            // in whatever encoding the sender used
+            let body = message
+                .body()
+                .map(|body| body.to_vec())
+                .ok_or(Mishap::MissingBody(uid));
+            fetched.push(Fetched {
+                id: uid,
+                mime: body,
+            });
+        }
+
+        Ok(fetched)
+    }
+
+    /// Published and skipped messages are archived or deleted; failed ones are set aside or left for a retry.
+    fn commit(&mut self, outcomes: Vec<(u32, Outcome)>) -> Result<(), Mishap> {
+        let settings = self.settings;
+        let capabilities = self.session.capabilities()?;
+        let can_move = capabilities.has_str("MOVE");
+        let can_uid_expunge = capabilities.has_str("UIDPLUS");
+
+        let mut deleted = Vec::new();
+        for (uid, outcome) in outcomes {
+            let uid_set = uid.to_string();
+
+            if outcome != Outcome::Failed {
+                self.session.uid_store(&uid_set, "+FLAGS (\\Seen)")?;
+            }
+
+            let destination = match outcome {
+                Outcome::Published | Outcome::Skipped => &settings.archive_mailbox,
+                Outcome::Failed => &settings.failed_mailbox,
+            };
+
+            match destination {
+                Some(mailbox) if can_move => self.session.uid_mv(&uid_set, mailbox)?,
+                Some(mailbox) => {
+                    self.session.uid_copy(&uid_set, quoted(mailbox))?;
+                    self.session.uid_store(&uid_set, "+FLAGS (\\Deleted)")?;
+                    deleted.push(uid);
+                }
+                None if outcome != Outcome::Failed && settings.expunge => {
+                    self.session.uid_store(&uid_set, "+FLAGS (\\Deleted)")?;
+                    deleted.push(uid);
+                }
+                None => {}
+            }
+        }
+
+        // Where we can, only expunge our own messages, not anything else marked as deleted
+        if !deleted.is_empty() {
+            let _msg_sequence_numbers = if can_uid_expunge {
+                self.session.uid_expunge(uid_set(deleted))?
+            } else {
+                self.session.expunge()?
+            };
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Mishap> {
+        self.session.logout()?;
+        Ok(())
+    }
+}
+
+fn quoted(mailbox: &str) -> String {
+    format!("\"{}\"", mailbox.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn uid_set<I: IntoIterator<Item = u32>>(uids: I) -> String {
+    let mut uids: Vec<u32> = uids.into_iter().collect();
+    uids.sort_unstable();
+    let uids: Vec<String> = uids.iter().map(|uid| uid.to_string()).collect();
+    uids.join(",")
+}
+
+/// The IMAP SEARCH query for the messages to turn into posts.
+fn search_query(settings: &Settings) -> String {
+    let mut criteria = Vec::new();
+
+    if settings.search_unseen {
+        criteria.push(String::from("UNSEEN"));
+    }
+    if let Some(from) = &settings.search_from {
+        criteria.push(format!("FROM {}", quoted(from)));
+    }
+    if let Some(to) = &settings.search_to {
+        criteria.push(format!("TO {}", quoted(to)));
+    }
+    if let Some(subject) = &settings.search_subject {
+        criteria.push(format!("SUBJECT {}", quoted(subject)));
+    }
+    if let Some(since) = &settings.search_since {
+        criteria.push(format!("SINCE {}", since.format("%-d-%b-%Y")));
+    }
+    if let Some(raw) = &settings.search_raw {
+        criteria.push(raw.clone());
+    }
+
+    if criteria.is_empty() {
+        String::from("ALL")
+    } else {
+        criteria.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_search_is_everything() {
+        assert_eq!("ALL", search_query(&Settings::for_test(&[])));
+    }
+
+    #[test]
+    fn test_search_criteria() {
+        let settings = Settings::for_test(&[
+            "--search-unseen",
+            "--search-from=alice@example.org",
+            "--search-to=blog+photos@example.org",
+            "--search-subject=Post: \"today\"",
+            "--search-since=2024-02-01",
+            "--search-raw=NOT FLAGGED",
+        ]);
+        assert_eq!(
+            r#"UNSEEN FROM "alice@example.org" TO "blog+photos@example.org" SUBJECT "Post: \"today\"" SINCE 1-Feb-2024 NOT FLAGGED"#,
+            search_query(&settings)
+        );
+    }
+
+    #[test]
+    fn test_uid_set() {
+        assert_eq!("7,9,12", uid_set([9, 12, 7]));
+    }
+}
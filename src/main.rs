@@ -2,17 +2,40 @@ use std::path::Path;
 
 use clap::Parser;
 mod settings;
+use blog::PostInfo;
 use log::debug;
-use settings::Settings;
+use mishaps::Mishap;
+use settings::{ImapAuth, Settings};
+use source::{Outcome, Source};
+use state::StateStore;
 use tempfile::TempDir;
 
+mod allowlist;
+mod authentication;
 mod blog;
+mod cleanup;
+mod connection;
 mod conventions;
+mod directives;
+mod dkim;
 mod dropbox;
 mod email;
+mod flowed;
+mod frontmatter;
+mod html;
 mod image;
+mod import;
+mod localmail;
+mod mailbox;
 mod mishaps;
+mod oauth;
+mod postingtoken;
+mod sanitise;
 mod signatureblock;
+mod source;
+mod state;
+mod subjectprefix;
+mod watch;
 
 fn main() {
     let settings = Settings::parse();
@@ -20,15 +43,70 @@ fn main() {
     env_logger::init();
     ensure_imagemagik_installed();
 
+    if !settings.trusted_senders.is_empty() && !authentication::verifies_sender(&settings) {
+        let reason = "needs --dkim-keys, or --trusted-authserv-id with dmarc in --require-auth";
+        stop(
+            "checking settings",
+            Mishap::BadSetting("trusted-sender", reason.to_string()),
+        );
+    }
+
+    if settings.output_profile.uses_media_path() && settings.media_path.is_none() {
+        stop("checking settings", Mishap::MissingSetting("media-path"));
+    }
+
+    // Each change to the mailbox fetches every message the search finds,
+    // so published emails must drop out of the search or be remembered between runs
+    let forgets_published = settings.expunge
+        || settings.archive_mailbox.is_some()
+        || settings.search_unseen
+        || settings.state_file.is_some();
+    if settings.watch && !forgets_published {
+        let reason = "needs --expunge, --archive-mailbox, --search-unseen or --state-file";
+        stop(
+            "checking settings",
+            Mishap::BadSetting("watch", reason.to_string()),
+        );
+    }
+
     let temp_dir = TempDir::new().expect("creating temporary directory");
     debug!("Writing to: {:?}", temp_dir.path());
     ensure_out_dir_exists(&settings, temp_dir.path());
 
-    if let Some(refresh) = &settings.dropbox_refresh_token {
+    let uses_imap = settings.input.is_none() && settings.import.is_none();
+    let needs_imap_token =
+        settings.imap_auth == ImapAuth::Xoauth2 && settings.imap_oauth_refresh_token.is_none();
+
+    if uses_imap && needs_imap_token {
+        // The IMAP OAuth2 dance works like the Dropbox one below: a code, if we have one,
+        // is exchanged for a refresh token; otherwise, show where to get a code
+        match &settings.imap_oauth_code {
+            Some(code) => show_imap_token(code, &settings),
+            None => match oauth::show_auth_url(&settings) {
+                Ok(url) => println!("{}", url),
+                Err(err) => stop("IMAP authorization URL", err),
+            },
+        }
+    } else if let Some(refresh) = &settings.dropbox_refresh_token {
         // If we have a refresh token, we're good to run
-        match dropblog(refresh, &settings, temp_dir.path()) {
-            Ok(count) => complete(count),
-            Err(err) => stop("dropblog processing", err),
+        if let Some(archive) = &settings.import {
+            match import::run(refresh, &settings, temp_dir.path(), archive) {
+                Ok(summary) => {
+                    println!("{}", summary);
+                    std::process::exit(0)
+                }
+                Err(err) => stop("importing archive", err),
+            }
+        } else if settings.watch {
+            match watch::run(refresh, &settings, temp_dir.path()) {
+                Ok(count) => complete(count),
+                Err(err) => stop("watching mailbox", err),
+            }
+        } else {
+            match dropblog(refresh, &settings, temp_dir.path()) {
+                Ok(count) => complete(count),
+                Err(err) => stop("dropblog processing", err),
+            }
         }
     } else if let Some(code) = &settings.dropbox_code {
         // If dropbox code is supplied, use it to fetch and print a refresh token
@@ -50,12 +128,14 @@ fn ensure_imagemagik_installed() {
 }
 
 fn ensure_out_dir_exists(settings: &Settings, out_dir: &Path) {
-    let media_dir = out_dir.join(&settings.media_path);
     let posts_dir = out_dir.join(&settings.posts_path);
 
-    if !media_dir.exists() {
-        std::fs::create_dir_all(media_dir).expect("creating media dir")
-    };
+    if let Some(media_path) = &settings.media_path {
+        let media_dir = out_dir.join(media_path);
+        if !media_dir.exists() {
+            std::fs::create_dir_all(media_dir).expect("creating media dir")
+        };
+    }
 
     if !posts_dir.exists() {
         std::fs::create_dir_all(posts_dir).expect("creating post dir")
@@ -69,33 +149,111 @@ fn show_token(code: &str, key: &str, secret: &str) {
     }
 }
 
+fn show_imap_token(code: &str, settings: &Settings) {
+    match oauth::get_refresh_token(code, settings) {
+        Ok(refresh) => println!("{}", refresh),
+        Err(err) => stop("IMAP token refresh", err),
+    }
+}
+
 fn dropblog(refresh: &str, settings: &Settings, out_dir: &Path) -> Result<usize, mishaps::Mishap> {
-    let extract = |msg| email::extract(settings, out_dir, msg);
-    let upload = |post| dropbox::upload(refresh, settings, &post);
+    let mut source = source::open(settings)?;
+    let mut state = StateStore::open(settings.state_file.as_deref())?;
+    let outcomes = process(refresh, settings, out_dir, &mut state, source.as_mut())?;
+    source.close()?;
+    Ok(published(&outcomes))
+}
 
-    let client = imap::ClientBuilder::new(&settings.hostname, settings.port).connect()?;
+/// Publish every message waiting in the source, returning how each one fared.
+fn process(
+    refresh: &str,
+    settings: &Settings,
+    out_dir: &Path,
+    state: &mut StateStore,
+    source: &mut dyn Source,
+) -> Result<Vec<Outcome>, mishaps::Mishap> {
+    let messages = source.fetch()?;
+    debug!("Fetched {} message(s)", messages.len());
 
-    let mut imap_session = client
-        .login(&settings.user, &settings.password)
-        .map_err(|(err, _client)| err)?;
+    // Each message is published (or fails) independently of the others.
+    // Nothing is moved or removed until we know how every message fared.
+    let mut outcomes = Vec::new();
+    for message in messages {
+        let mime = match message.mime {
+            Ok(mime) => mime,
+            Err(err) => {
+                report("reading message", err);
+                outcomes.push((message.id, Outcome::Failed));
+                continue;
+            }
+        };
 
-    imap_session.select(&settings.mailbox)?;
+        // A crash after uploading but before committing would otherwise publish the email twice
+        let key = state_key(&mime);
+        if let Some(key) = key.as_ref().filter(|key| state.contains(key)) {
+            debug!("Already published {}", key);
+            outcomes.push((message.id, Outcome::Skipped));
+            continue;
+        }
 
-    let result = match email::fetch(settings, &mut imap_session)? {
-        None => Ok(0), // No messages to process
-        Some(mime_message) => email::parse(&mime_message)
-            .and_then(extract)
-            .and_then(blog::write)
-            .and_then(upload),
-    };
+        let outcome = match publish(refresh, settings, out_dir, state, &mime) {
+            Ok(post) => {
+                if let Some(key) = &key {
+                    state.record(key, &post)?;
+                }
+                Outcome::Published
+            }
+            Err(err) => {
+                report("message processing", err);
+                Outcome::Failed
+            }
+        };
+        outcomes.push((message.id, outcome));
+    }
 
-    imap_session.logout()?;
+    let fared = outcomes.iter().map(|(_, outcome)| *outcome).collect();
+    source.commit(outcomes)?;
 
-    result
+    Ok(fared)
 }
 
-fn stop<E: std::fmt::Display>(context: &str, err: E) -> ! {
+/// The number of posts actually published, not counting those skipped.
+fn published(outcomes: &[Outcome]) -> usize {
+    outcomes
+        .iter()
+        .filter(|outcome| **outcome == Outcome::Published)
+        .count()
+}
+
+fn publish(
+    refresh: &str,
+    settings: &Settings,
+    out_dir: &Path,
+    state: &StateStore,
+    mime_message: &[u8],
+) -> Result<PostInfo, mishaps::Mishap> {
+    let extract = |msg| email::extract(settings, out_dir, state, msg);
+    let upload = |post| dropbox::upload(refresh, settings, &post).map(|_| post);
+
+    email::parse(mime_message)
+        .and_then(extract)
+        .and_then(|post| blog::write(settings, post))
+        .and_then(upload)
+}
+
+/// What the state store knows an email by, if it can be parsed.
+fn state_key(mime_message: &[u8]) -> Option<String> {
+    email::parse(mime_message)
+        .ok()
+        .map(|mail| email::state_key(&mail))
+}
+
+fn report<E: std::fmt::Display>(context: &str, err: E) {
     eprintln!("Failed: {} at {}", err, context);
+}
+
+fn stop<E: std::fmt::Display>(context: &str, err: E) -> ! {
+    report(context, err);
     std::process::exit(1)
 }
 
@@ -6,15 +6,42 @@ pub enum Mishap {
     #[error(transparent)]
     Imap(#[from] imap::error::Error),
 
+    #[error("Missing setting: --{0}")]
+    MissingSetting(&'static str),
+
+    #[error("Bad setting --{0}: {1}")]
+    BadSetting(&'static str, String),
+
+    #[error("TLS setup failed: {0}")]
+    Tls(String),
+
+    #[error("Refusing an unencrypted IMAP connection to {0} (see --allow-plaintext)")]
+    PlaintextRefused(String),
+
     #[error(transparent)]
     Email(#[from] mailparse::MailParseError),
 
+    #[error("Message {0} has no body")]
+    MissingBody(u32),
+
     #[error("Bad email field: {0}")]
     EmailField(String),
 
+    #[error("Sender not allowed to post: {0:?}")]
+    SenderRefused(String),
+
+    #[error("No valid posting token in the recipient address or subject")]
+    MissingPostingToken,
+
+    #[error("Sender authentication failed: {0}={1}")]
+    AuthenticationFailed(String, String),
+
     #[error(transparent)]
     File(#[from] std::io::Error),
 
+    #[error("Template failed: {0}")]
+    Template(#[from] minijinja::Error),
+
     #[error(transparent)]
     DropboxConnection(#[from] reqwest::Error),
 
@@ -0,0 +1,106 @@
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use std::time::Duration;
+
+// OAuth2 for IMAP (e.g., Gmail or Outlook), following the same offline flow as for Dropbox:
+// the user visits a consent URL once, and the code they get back is exchanged for a refresh token.
+
+pub fn show_auth_url(settings: &Settings) -> Result<String, Mishap> {
+    let client_id = client_id(settings)?;
+    let params = [
+        ("client_id", client_id),
+        ("redirect_uri", &settings.imap_oauth_redirect_uri),
+        ("response_type", "code"),
+        ("scope", &settings.imap_oauth_scope),
+        ("access_type", "offline"),
+        ("prompt", "consent"),
+    ];
+    reqwest::Url::parse_with_params(&settings.imap_oauth_auth_url, &params)
+        .map(|url| url.to_string())
+        .map_err(|err| Mishap::BadSetting("imap-oauth-auth-url", err.to_string()))
+}
+
+pub fn get_refresh_token(code: &str, settings: &Settings) -> Result<String, Mishap> {
+    let form = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &settings.imap_oauth_redirect_uri),
+    ];
+    token_request(settings, &form).and_then(|tr| {
+        tr.refresh_token.ok_or(Mishap::JsonContent(
+            String::new(),
+            "no refresh_token".to_string(),
+        ))
+    })
+}
+
+// Use the long-lived refresh token to fetch a short-lived access token for IMAP
+pub fn access_token(settings: &Settings) -> Result<String, Mishap> {
+    let refresh_token = settings
+        .imap_oauth_refresh_token
+        .as_ref()
+        .ok_or(Mishap::MissingSetting("imap-oauth-refresh-token"))?;
+    let form = [
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+    ];
+    token_request(settings, &form).map(|tr| tr.access_token)
+}
+
+/// SASL XOAUTH2, as used by Gmail and Outlook.
+pub struct XOAuth2 {
+    pub user: String,
+    pub access_token: String,
+}
+
+impl imap::Authenticator for XOAuth2 {
+    type Response = String;
+
+    fn process(&self, _challenge: &[u8]) -> Self::Response {
+        format!(
+            "user={}\x01auth=Bearer {}\x01\x01",
+            self.user, self.access_token
+        )
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn token_request(settings: &Settings, form: &[(&str, &str)]) -> Result<TokenResponse, Mishap> {
+    let client_id = client_id(settings)?;
+    let client_secret = settings
+        .imap_oauth_client_secret
+        .as_deref()
+        .unwrap_or_default();
+
+    let mut params = vec![("client_id", client_id), ("client_secret", client_secret)];
+    params.extend_from_slice(form);
+
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&settings.imap_oauth_token_url)
+        .form(&params)
+        .timeout(TIMEOUT)
+        .send()?;
+
+    let body = response.text()?;
+    let tr: Result<TokenResponse, _> = serde_json::from_str(&body);
+
+    match tr {
+        Ok(tr) => Ok(tr),
+        Err(err) => Err(Mishap::JsonContent(body, err.to_string())),
+    }
+}
+
+fn client_id(settings: &Settings) -> Result<&str, Mishap> {
+    settings
+        .imap_oauth_client_id
+        .as_deref()
+        .ok_or(Mishap::MissingSetting("imap-oauth-client-id"))
+}
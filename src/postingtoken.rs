@@ -0,0 +1,107 @@
+use regex::Regex;
+use std::str::FromStr;
+
+/// A shared secret that must accompany a post, optionally naming the author it stands for.
+/// Written as `token` or `token=Author Name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostingToken {
+    pub token: String,
+    pub author: Option<String>,
+}
+
+impl FromStr for PostingToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (token, author) = match s.split_once('=') {
+            Some((token, author)) => (token.trim(), Some(author.trim().to_string())),
+            None => (s.trim(), None),
+        };
+
+        if token.is_empty() {
+            Err(String::from("posting token cannot be empty"))
+        } else {
+            Ok(PostingToken {
+                token: token.to_string(),
+                author: author.filter(|a| !a.is_empty()),
+            })
+        }
+    }
+}
+
+/// Find the posting token, either as a plus-address on one of the recipients (`blog+token@example.org`)
+/// or as a `[token]` tag in the subject.
+/// Returns the matching token (if any) and the subject with the tag removed.
+pub fn find<'a>(
+    tokens: &'a [PostingToken],
+    recipients: &[String],
+    subject: Option<String>,
+) -> (Option<&'a PostingToken>, Option<String>) {
+    let plus_addressed = tokens.iter().find(|t| {
+        recipients
+            .iter()
+            .filter_map(|addr| addr.split_once('@'))
+            .filter_map(|(local, _)| local.split_once('+'))
+            .any(|(_, tag)| tag == t.token)
+    });
+
+    let mut tagged = None;
+    let subject = subject.map(|text| {
+        let mut text = text;
+        for t in tokens.iter() {
+            let pattern = Regex::new(&format!(r"\s*\[{}\]\s*", regex::escape(&t.token))).unwrap();
+            if pattern.is_match(&text) {
+                tagged = tagged.or(Some(t));
+                text = pattern.replace_all(&text, " ").trim().to_string();
+            }
+        }
+        text
+    });
+
+    (plus_addressed.or(tagged), subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> Vec<PostingToken> {
+        vec!["s3cret=Alice".parse().unwrap(), "t0ken".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let expected = PostingToken {
+            token: "s3cret".to_string(),
+            author: Some("Alice Smith".to_string()),
+        };
+        assert_eq!(Ok(expected), "s3cret=Alice Smith".parse());
+        assert!("=Alice".parse::<PostingToken>().is_err());
+    }
+
+    #[test]
+    fn test_plus_address() {
+        let tokens = tokens();
+        let recipients = vec!["blog+s3cret@example.org".to_string()];
+        let (found, subject) = find(&tokens, &recipients, Some("Lunch".to_string()));
+        assert_eq!(Some(&tokens[0]), found);
+        assert_eq!(Some("Lunch".to_string()), subject);
+    }
+
+    #[test]
+    fn test_subject_tag_is_stripped() {
+        let tokens = tokens();
+        let subject = Some("[t0ken] Lunch in [Paris]".to_string());
+        let (found, subject) = find(&tokens, &[], subject);
+        assert_eq!(Some(&tokens[1]), found);
+        assert_eq!(Some("Lunch in [Paris]".to_string()), subject);
+    }
+
+    #[test]
+    fn test_no_token() {
+        let tokens = tokens();
+        let recipients = vec!["blog+wrong@example.org".to_string()];
+        let (found, _) = find(&tokens, &recipients, Some("[wrong] Lunch".to_string()));
+        assert_eq!(None, found);
+    }
+}
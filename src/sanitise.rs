@@ -0,0 +1,96 @@
+use regex::{Captures, Regex};
+
+use super::html::is_script_url;
+use super::settings::OutputProfile;
+
+/// Stop the site generator running template code written in the email.
+/// Jekyll (Liquid) and Eleventy (Liquid or Nunjucks) get each delimiter wrapped in `{% raw %}`,
+/// which also works inside code blocks.
+/// Hugo shortcodes and Zola (Tera) shortcodes get the first brace written as an HTML entity.
+pub fn template_syntax(profile: OutputProfile, text: &str) -> String {
+    template_delimiter()
+        .replace_all(text, |caps: &Captures| match profile {
+            OutputProfile::Jekyll | OutputProfile::Eleventy => {
+                format!("{{% raw %}}{}{{% endraw %}}", &caps[0])
+            }
+            OutputProfile::Hugo | OutputProfile::Zola => format!("&#123;{}", &caps[0][1..]),
+        })
+        .to_string()
+}
+
+/// Whether the text holds template code (`{{`, `{%` or `{#`).
+pub fn has_template_syntax(text: &str) -> bool {
+    template_delimiter().is_match(text)
+}
+
+fn template_delimiter() -> Regex {
+    Regex::new(r"\{[{%#]").unwrap()
+}
+
+/// Turn HTML tags in the text into text, so `<script>` is shown rather than run.
+/// A `<` that does not start a tag, as in `a < b` or `<3`, is left alone,
+/// and one already escaped as `\<` is replaced too, so it is not shown as `\&lt;`.
+pub fn html(text: &str) -> String {
+    let tag = Regex::new(r"\\?<([A-Za-z/!?])").unwrap();
+    tag.replace_all(text, "&lt;$1").to_string()
+}
+
+/// Remove Markdown links to `javascript:` (and similar) URLs, keeping the link text.
+pub fn links(text: &str) -> String {
+    let keep_text = |caps: &Captures| {
+        if is_script_url(&caps["url"]) {
+            caps.name("text").map_or("", |m| m.as_str()).to_string()
+        } else {
+            caps[0].to_string()
+        }
+    };
+
+    // The URL may hold balanced parentheses, as in `javascript:alert(1)`
+    let inline =
+        Regex::new(r"\[(?<text>[^\]]*)\]\(\s*(?<url>[^()\s]*(\([^()\s]*\)[^()\s]*)*)[^)]*\)")
+            .unwrap();
+    let text = inline.replace_all(text, keep_text);
+
+    let reference = Regex::new(r"(?m)^ {0,3}\[[^\]]+\]:\s*(?<url>\S+).*$").unwrap();
+    reference.replace_all(&text, keep_text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liquid_escaped() {
+        assert_eq!(
+            "Hi {% raw %}{{{% endraw %} site.url }} and {% raw %}{%{% endraw %} include x.html %}",
+            template_syntax(
+                OutputProfile::Jekyll,
+                "Hi {{ site.url }} and {% include x.html %}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_shortcodes_escaped() {
+        assert_eq!(
+            "&#123;{< youtube x >}} &#123;% note() %}",
+            template_syntax(OutputProfile::Hugo, "{{< youtube x >}} {% note() %}")
+        );
+    }
+
+    #[test]
+    fn test_html_tags() {
+        assert_eq!(
+            "&lt;script>alert(1)&lt;/script> but 1 < 2 <3",
+            html("<script>alert(1)</script> but 1 < 2 <3")
+        );
+    }
+
+    #[test]
+    fn test_script_links() {
+        assert_eq!(
+            "click and [safe](https://example.org)\n\n",
+            links("[click](javascript:alert(1)) and [safe](https://example.org)\n\n[x]: javascript:alert(1)")
+        );
+    }
+}
@@ -1,4 +1,10 @@
-use clap::Parser;
+use chrono::NaiveDate;
+use clap::{Parser, ValueEnum};
+use regex::Regex;
+use std::path::PathBuf;
+
+use super::postingtoken::PostingToken;
+use super::subjectprefix::SubjectPrefix;
 
 #[derive(Debug, Parser)]
 pub struct Settings {
@@ -10,18 +16,193 @@ pub struct Settings {
     #[arg(long, default_value = "993", env = "IMAP_PORT")]
     pub port: u16,
 
+    /// How to secure the IMAP connection
+    #[arg(long, env = "IMAP_TLS", value_enum, default_value = "implicit")]
+    pub imap_tls: ImapTls,
+
+    /// PEM file of extra CA certificates to trust for IMAP (e.g., for a private server)
+    #[arg(long, env = "IMAP_CA_FILE")]
+    pub imap_ca_file: Option<PathBuf>,
+
+    /// Allow an unencrypted IMAP connection to a server that is not on this machine
+    #[arg(long, env = "IMAP_ALLOW_PLAINTEXT")]
+    pub allow_plaintext: bool,
+
     /// Email address (or user account) to check on the IMAP server
-    #[arg(long, env = "IMAP_USER")]
-    pub user: String,
+    #[arg(long, env = "IMAP_USER", required_unless_present_any = ["input", "import"])]
+    pub user: Option<String>,
 
-    /// Password for authentication
+    /// Password for authentication (when --imap-auth is password)
     #[arg(long, env = "IMAP_PASSWORD", hide_env_values = true)]
-    pub password: String,
+    pub password: Option<String>,
+
+    /// How to authenticate with the IMAP server
+    #[arg(long, env = "IMAP_AUTH", value_enum, default_value = "password")]
+    pub imap_auth: ImapAuth,
+
+    /// OAuth2 client ID for IMAP
+    #[arg(long, env = "IMAP_OAUTH_CLIENT_ID")]
+    pub imap_oauth_client_id: Option<String>,
+
+    /// OAuth2 client secret for IMAP
+    #[arg(long, env = "IMAP_OAUTH_CLIENT_SECRET", hide_env_values = true)]
+    pub imap_oauth_client_secret: Option<String>,
+
+    /// OAuth2 refresh token for IMAP
+    #[arg(long, env = "IMAP_OAUTH_REFRESH_TOKEN", hide_env_values = true)]
+    pub imap_oauth_refresh_token: Option<String>,
+
+    /// OAuth2 code (supplied by user, used once to fetch an IMAP refresh token)
+    #[arg(long, env = "IMAP_OAUTH_CODE", hide_env_values = true)]
+    pub imap_oauth_code: Option<String>,
+
+    /// OAuth2 consent URL for IMAP
+    #[arg(
+        long,
+        env = "IMAP_OAUTH_AUTH_URL",
+        default_value = "https://accounts.google.com/o/oauth2/v2/auth"
+    )]
+    pub imap_oauth_auth_url: String,
+
+    /// OAuth2 token URL for IMAP
+    #[arg(
+        long,
+        env = "IMAP_OAUTH_TOKEN_URL",
+        default_value = "https://oauth2.googleapis.com/token"
+    )]
+    pub imap_oauth_token_url: String,
+
+    /// OAuth2 scope for IMAP access
+    #[arg(
+        long,
+        env = "IMAP_OAUTH_SCOPE",
+        default_value = "https://mail.google.com/"
+    )]
+    pub imap_oauth_scope: String,
+
+    /// OAuth2 redirect URI registered for the client (the code is in the URL it redirects to)
+    #[arg(
+        long,
+        env = "IMAP_OAUTH_REDIRECT_URI",
+        default_value = "http://localhost"
+    )]
+    pub imap_oauth_redirect_uri: String,
 
     // The mailbox to read from
     #[arg(short, long, env = "MAILBOX", default_value = "INBOX")]
     pub mailbox: String,
 
+    /// Only process unread emails
+    #[arg(long, env = "SEARCH_UNSEEN")]
+    pub search_unseen: bool,
+
+    /// Only process emails from this address
+    #[arg(long, env = "SEARCH_FROM")]
+    pub search_from: Option<String>,
+
+    /// Only process emails to this address (e.g., a plus-address)
+    #[arg(long, env = "SEARCH_TO")]
+    pub search_to: Option<String>,
+
+    /// Only process emails whose subject contains this text
+    #[arg(long, env = "SEARCH_SUBJECT")]
+    pub search_subject: Option<String>,
+
+    /// Only process emails received on or after this date (YYYY-MM-DD)
+    #[arg(long, env = "SEARCH_SINCE")]
+    pub search_since: Option<NaiveDate>,
+
+    /// Extra IMAP SEARCH criteria, added as given (e.g., "NOT FLAGGED")
+    #[arg(long, env = "SEARCH_RAW")]
+    pub search_raw: Option<String>,
+
+    /// Read emails from local files (an .eml file, a directory of them, an mbox, or a Maildir) instead of IMAP
+    #[arg(long, env = "INPUT", conflicts_with = "watch")]
+    pub input: Option<PathBuf>,
+
+    /// Import every email in a mail archive (as for --input), skipping any already imported
+    #[arg(long, conflicts_with_all = ["input", "watch"], requires = "state_file")]
+    pub import: Option<PathBuf>,
+
+    /// Format of the --input or --import files
+    #[arg(long, env = "INPUT_FORMAT", value_enum, default_value = "auto")]
+    pub input_format: InputFormat,
+
+    /// Sender addresses or domains allowed to post (everyone, if none are given)
+    #[arg(long = "allow-sender", env = "ALLOWED_SENDERS", value_delimiter = ',')]
+    pub allow_senders: Vec<String>,
+
+    /// Only trust Authentication-Results headers added by this server (its authserv-id)
+    #[arg(long, env = "TRUSTED_AUTHSERV_ID")]
+    pub trusted_authserv_id: Option<String>,
+
+    /// Mechanisms that must pass in the trusted Authentication-Results
+    #[arg(
+        long,
+        env = "REQUIRE_AUTH",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "dmarc"
+    )]
+    pub require_auth: Vec<AuthMechanism>,
+
+    /// File of DKIM public keys (name and TXT record per line) to verify signatures offline
+    #[arg(long, env = "DKIM_KEYS")]
+    pub dkim_keys: Option<PathBuf>,
+
+    /// Secret tokens required to post, as `token` or `token=Author`
+    #[arg(
+        long = "posting-token",
+        env = "POSTING_TOKENS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub posting_tokens: Vec<PostingToken>,
+
+    /// Use the HTML part of an email, converted to Markdown, even when there is a plain text part
+    #[arg(long, env = "PREFER_HTML")]
+    pub prefer_html: bool,
+
+    /// Sender addresses or domains whose `-- ` signatures are kept in their posts
+    #[arg(
+        long = "keep-signature",
+        env = "KEEP_SIGNATURE_SENDERS",
+        value_delimiter = ','
+    )]
+    pub keep_signature_senders: Vec<String>,
+
+    /// Sender addresses or domains whose HTML and template code (such as Liquid tags) are kept in their posts
+    #[arg(
+        long = "trusted-sender",
+        env = "TRUSTED_SENDERS",
+        value_delimiter = ','
+    )]
+    pub trusted_senders: Vec<String>,
+
+    /// Built-in rules for removing replies and footers from the post text
+    #[arg(
+        long,
+        env = "CLEANUP",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "reply-header,mobile-footer"
+    )]
+    pub cleanup: Vec<CleanupRule>,
+
+    /// Regular expression for other text to remove from posts (may be repeated)
+    #[arg(long = "cleanup-pattern")]
+    pub cleanup_patterns: Vec<Regex>,
+
+    /// Subject line markers, as `marker=path:DIR` (post location) or `marker=KEY:VALUE` (front matter)
+    /// [default: draft=path:_drafts,note=layout:note for Jekyll; draft=draft:true,note=template:note.html
+    /// for Zola; draft=draft:true,note=layout:note otherwise]
+    #[arg(
+        long = "subject-prefix",
+        env = "SUBJECT_PREFIXES",
+        value_delimiter = ','
+    )]
+    pub subject_prefixes: Vec<SubjectPrefix>,
+
     /// Dropbox refresh token
     #[arg(long, env, hide_env_values = true)]
     pub dropbox_refresh_token: Option<String>,
@@ -38,23 +219,163 @@ pub struct Settings {
     #[arg(long, env, hide_env_values = true)]
     pub dropbox_app_secret: String,
 
-    /// Path into media relative to OUT_DIR
+    /// Path into media relative to OUT_DIR (Jekyll and Eleventy only: page bundles keep images with the post)
     #[arg(long, env = "MEDIA_PATH")]
-    pub media_path: String,
+    pub media_path: Option<String>,
 
     /// Path into posts relative to OUT_DIR
     #[arg(long, env = "POSTS_PATH")]
     pub posts_path: String,
 
+    /// The static site generator to write posts for
+    #[arg(long, value_enum, default_value = "jekyll", env = "OUTPUT_PROFILE")]
+    pub output_profile: OutputProfile,
+
+    /// MiniJinja template for posts (instead of the output profile's)
+    #[arg(long, env = "POST_TEMPLATE")]
+    pub post_template: Option<PathBuf>,
+
+    /// MiniJinja template for each image in a post (instead of the output profile's)
+    #[arg(long, env = "IMAGE_TEMPLATE")]
+    pub image_template: Option<PathBuf>,
+
     /// Thumbnail width
     #[arg(short, long, default_value = "500", env = "IMAGE_WIDTH")]
     pub width: u16,
 
-    /// Archive the email after processing
-    #[arg(short, long, env = "EXPURGE")]
+    /// Delete the email after processing
+    #[arg(short, long, env = "EXPURGE", conflicts_with = "archive_mailbox")]
     pub expunge: bool,
 
+    /// Move the email to this mailbox after processing (created if missing)
+    #[arg(long, env = "ARCHIVE_MAILBOX")]
+    pub archive_mailbox: Option<String>,
+
+    /// Move emails that could not be published to this mailbox (created if missing)
+    #[arg(long, env = "FAILED_MAILBOX")]
+    pub failed_mailbox: Option<String>,
+
+    /// JSON file recording each email published (by Message-ID), so none is published twice
+    #[arg(long, env = "STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Keep running, publishing new emails as they arrive
+    #[arg(long, env = "WATCH")]
+    pub watch: bool,
+
+    /// Seconds between mailbox checks when watching a server without IDLE support
+    #[arg(long, default_value = "60", env = "POLL_INTERVAL")]
+    pub poll_interval: u64,
+
     /// Outline the structure of the email as additional output
     #[arg(long)]
     pub show_outline: bool,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImapTls {
+    /// TLS from the start (usually port 993)
+    Implicit,
+    /// Upgrade a plain connection with STARTTLS (usually port 143)
+    Starttls,
+    /// No encryption
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImapAuth {
+    Password,
+    Xoauth2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuthMechanism {
+    Dkim,
+    Spf,
+    Dmarc,
+}
+
+impl AuthMechanism {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthMechanism::Dkim => "dkim",
+            AuthMechanism::Spf => "spf",
+            AuthMechanism::Dmarc => "dmarc",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CleanupRule {
+    /// An "On ... wrote:" or "Original Message" line, and the reply below it
+    ReplyHeader,
+    /// Lines starting with `>` (including quotes written on purpose)
+    QuotedLines,
+    /// "Sent from my iPhone", "Get Outlook for Android" and the like
+    MobileFooter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputProfile {
+    /// Dated files in POSTS_PATH, images in MEDIA_PATH by year
+    Jekyll,
+    /// Page bundles (POSTS_PATH/slug/index.md) with the images alongside, TOML front matter
+    Hugo,
+    /// Page bundles (POSTS_PATH/slug/index.md) with the images alongside, TOML front matter
+    Zola,
+    /// Dated files in POSTS_PATH, images in MEDIA_PATH by year
+    Eleventy,
+}
+
+impl OutputProfile {
+    /// Whether images go under `--media-path`, rather than alongside the post.
+    pub fn uses_media_path(&self) -> bool {
+        matches!(self, OutputProfile::Jekyll | OutputProfile::Eleventy)
+    }
+
+    /// The subject line markers used when none are given.
+    /// Only Jekyll has a drafts directory; the others mark a draft in the front matter.
+    /// Zola picks a page's template with `template` rather than `layout`.
+    pub fn subject_prefixes(&self) -> Vec<SubjectPrefix> {
+        let draft = match self {
+            OutputProfile::Jekyll => "draft=path:_drafts",
+            _ => "draft=draft:true",
+        };
+        let note = match self {
+            OutputProfile::Zola => "note=template:note.html",
+            _ => "note=layout:note",
+        };
+        [draft, note]
+            .iter()
+            .map(|prefix| prefix.parse().unwrap())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Work it out from the path
+    Auto,
+    /// A single .eml file
+    Eml,
+    /// A directory of .eml files
+    EmlDir,
+    Mbox,
+    Maildir,
+}
+
+#[cfg(test)]
+impl Settings {
+    /// Settings for tests: the required arguments, followed by the given ones.
+    pub fn for_test(args: &[&str]) -> Settings {
+        let required = [
+            "dropblog",
+            "--user=u",
+            "--dropbox-app-key=k",
+            "--dropbox-app-secret=s",
+            "--media-path=media",
+            "--posts-path=_posts",
+        ];
+        Settings::parse_from(required.iter().chain(args.iter()))
+    }
+}
@@ -1,10 +1,21 @@
-use regex::Regex;
-
 /// Remove the `-- ` signature from a message.
+/// Only a line of exactly `-- ` (or `--`) counts, so Markdown rules like `---` are left alone.
+/// If there are several, the signature starts at the last one.
 /// https://en.wikipedia.org/wiki/Signature_block#Standard_delimiter
 pub fn remove(str: String) -> String {
-    let pattern = Regex::new(r"(?m)^-- ?(?s).*$").unwrap();
-    pattern.replace_all(&str, "").to_string()
+    let mut offset = 0;
+    let mut delimiter = None;
+    for line in str.split_inclusive('\n') {
+        if matches!(line.trim_end_matches(['\r', '\n']), "-- " | "--") {
+            delimiter = Some(offset);
+        }
+        offset += line.len();
+    }
+
+    match delimiter {
+        Some(start) => str[..start].to_string(),
+        None => str,
+    }
 }
 
 #[cfg(test)]
@@ -34,4 +45,31 @@ mod tests {
             remove(input.to_string())
         );
     }
+
+    #[test]
+    fn test_markdown_rule_kept() {
+        let input = "Intro\n\n---\n\nMore\n\n----\n\nEnd\n";
+        assert_eq!(input.to_string(), remove(input.to_string()));
+    }
+
+    #[test]
+    fn test_dashes_within_line_kept() {
+        let input = "Dear Alice,\n-- not a signature\nBye\n";
+        assert_eq!(input.to_string(), remove(input.to_string()));
+    }
+
+    #[test]
+    fn test_last_delimiter_used() {
+        let input = "Text\n--\nStill text\n-- \nEddie\n";
+        assert_eq!(
+            "Text\n--\nStill text\n".to_string(),
+            remove(input.to_string())
+        );
+    }
+
+    #[test]
+    fn test_crlf_sig() {
+        let input = "Dear Alice,\r\n-- \r\nEddie\r\n";
+        assert_eq!("Dear Alice,\r\n".to_string(), remove(input.to_string()));
+    }
 }
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use super::localmail;
+use super::mailbox::Imap;
+use super::mishaps::Mishap;
+use super::settings::{InputFormat, Settings};
+
+/// A message read from a source, but not yet marked as processed.
+/// The `id` only has meaning to the source it came from.
+/// A message that could not be read still has an `id`, so it can be set aside like any failure.
+pub struct Fetched {
+    pub id: u32,
+    pub mime: Result<Vec<u8>, Mishap>,
+}
+
+/// Whether a fetched message made it all the way to a published post.
+/// A message that was already published is skipped, but disposed of as if just published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Published,
+    Skipped,
+    Failed,
+}
+
+/// Somewhere emails come from: an IMAP mailbox, or local files.
+pub trait Source {
+    /// Every message waiting to be published, oldest first.
+    fn fetch(&mut self) -> Result<Vec<Fetched>, Mishap>;
+
+    /// Dispose of messages once we know how processing went.
+    fn commit(&mut self, outcomes: Vec<(u32, Outcome)>) -> Result<(), Mishap>;
+
+    /// Release any connection held by the source.
+    fn close(&mut self) -> Result<(), Mishap> {
+        Ok(())
+    }
+}
+
+/// The source given by the settings: local files if `--input` is set, otherwise IMAP.
+pub fn open(settings: &Settings) -> Result<Box<dyn Source + '_>, Mishap> {
+    match &settings.input {
+        None => Ok(Box::new(Imap::connect(settings)?)),
+        Some(path) => Ok(open_local(path, settings.input_format)),
+    }
+}
+
+/// Emails in local files, detecting the format if need be.
+pub fn open_local(path: &Path, format: InputFormat) -> Box<dyn Source> {
+    let format = match format {
+        InputFormat::Auto => detect(path),
+        format => format,
+    };
+    localmail::open(path, format)
+}
+
+fn detect(path: &Path) -> InputFormat {
+    if path.join("cur").is_dir() && path.join("new").is_dir() {
+        InputFormat::Maildir
+    } else if path.is_dir() {
+        InputFormat::EmlDir
+    } else if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+    {
+        InputFormat::Eml
+    } else {
+        InputFormat::Mbox
+    }
+}
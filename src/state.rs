@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::blog::PostInfo;
+use super::mishaps::Mishap;
+
+/// A record of every post published, keyed by the Message-ID of the email it came from
+/// (or, for an email without one, a digest of the email).
+/// Without a file path, the store only lasts for the current run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateStore {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    posts: BTreeMap<String, Record>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub permalink: String,
+    pub post_path: String,
+    pub media: Vec<String>,
+    pub published: DateTime<Utc>,
+}
+
+impl StateStore {
+    /// Load the store from a JSON file. A missing file is an empty store.
+    pub fn open(path: Option<&Path>) -> Result<StateStore, Mishap> {
+        let mut store = match path {
+            Some(file) if file.exists() => {
+                let json = fs::read_to_string(file)?;
+                serde_json::from_str(&json)
+                    .map_err(|err| Mishap::JsonContent(json, err.to_string()))?
+            }
+            _ => StateStore::default(),
+        };
+        store.path = path.map(Path::to_path_buf);
+        Ok(store)
+    }
+
+    pub fn contains(&self, message_id: &str) -> bool {
+        self.posts.contains_key(message_id)
+    }
+
+    pub fn permalink_taken(&self, permalink: &str) -> bool {
+        self.posts.values().any(|r| r.permalink == permalink)
+    }
+
+    /// Remember a published post under the email's key (see `email::state_key`),
+    /// saving the store if it has a file.
+    pub fn record(&mut self, key: &str, post: &PostInfo) -> Result<(), Mishap> {
+        let media = post
+            .attachments
+            .iter()
+            .flat_map(|image| [&image.relative_path, &image.thumbnail.relative_path])
+            .cloned()
+            .collect();
+
+        let record = Record {
+            permalink: post.permalink.clone(),
+            post_path: post.relative_path.clone(),
+            media,
+            published: Utc::now(),
+        };
+
+        self.posts.insert(key.to_string(), record);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Mishap> {
+        if let Some(file) = &self.path {
+            let json = serde_json::to_string_pretty(self)
+                .map_err(|err| Mishap::JsonContent(String::new(), err.to_string()))?;
+
+            // Write then rename, so a crash cannot leave a half-written store
+            let temp = file.with_extension("tmp");
+            fs::write(&temp, json)?;
+            fs::rename(temp, file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_survives_reopening() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("state.json");
+
+        let post = PostInfo::new(
+            "Lunch".to_string(),
+            "Alice".to_string(),
+            None,
+            Utc::now(),
+            "/lunch".to_string(),
+            Vec::new(),
+            "_posts/2020-01-15-lunch.md".to_string(),
+            PathBuf::new(),
+            Vec::new(),
+            Some("<1234@example.org>".to_string()),
+        );
+
+        let mut store = StateStore::open(Some(&file)).unwrap();
+        assert!(!store.contains("<1234@example.org>"));
+        store.record("<1234@example.org>", &post).unwrap();
+
+        let reopened = StateStore::open(Some(&file)).unwrap();
+        assert!(reopened.contains("<1234@example.org>"));
+        assert!(reopened.permalink_taken("/lunch"));
+        assert!(!reopened.permalink_taken("/dinner"));
+    }
+}
@@ -0,0 +1,144 @@
+use regex::Regex;
+use std::str::FromStr;
+
+/// What a `[marker]` at the start of the subject does to the post.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixAction {
+    /// Write the post under this path (relative to OUT_DIR) instead of `--posts-path`
+    Path(String),
+    /// Set a front matter field, e.g., the layout
+    FrontMatter(String, String),
+}
+
+/// A subject line marker, written as `marker=path:DIR` or `marker=KEY:VALUE`.
+/// E.g., `draft=path:_drafts` or `note=layout:note`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectPrefix {
+    pub marker: String,
+    pub action: PrefixAction,
+}
+
+impl FromStr for SubjectPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected marker=path:DIR or marker=KEY:VALUE, not {:?}", s);
+
+        let (marker, action) = s.split_once('=').ok_or_else(invalid)?;
+        let (key, value) = action.split_once(':').ok_or_else(invalid)?;
+        let (marker, key, value) = (marker.trim(), key.trim(), value.trim());
+        if marker.is_empty() || key.is_empty() || value.is_empty() {
+            return Err(invalid());
+        }
+
+        let action = match key {
+            "path" => PrefixAction::Path(value.to_string()),
+            _ => PrefixAction::FrontMatter(key.to_string(), value.to_string()),
+        };
+        Ok(SubjectPrefix {
+            marker: marker.to_lowercase(),
+            action,
+        })
+    }
+}
+
+/// What the subject line asked for.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Steering {
+    pub posts_path: Option<String>,
+    pub front_matter: Vec<(String, String)>,
+    pub tags: Vec<String>,
+}
+
+/// Take the known `[marker]` prefixes and `#hashtags` out of the subject.
+/// Returns what they asked for, and the subject without them.
+pub fn parse(prefixes: &[SubjectPrefix], subject: Option<String>) -> (Steering, Option<String>) {
+    let mut steering = Steering::default();
+
+    let subject = subject.map(|text| {
+        let marker = Regex::new(r"^\s*\[([^\]]+)\]").unwrap();
+        let mut text = text.as_str();
+        while let Some(caps) = marker.captures(text) {
+            let name = caps[1].trim().to_lowercase();
+            let prefix = match prefixes.iter().find(|p| p.marker == name) {
+                Some(prefix) => prefix,
+                None => break,
+            };
+            match &prefix.action {
+                PrefixAction::Path(path) => steering.posts_path = Some(path.clone()),
+                PrefixAction::FrontMatter(key, value) => {
+                    steering.front_matter.push((key.clone(), value.clone()))
+                }
+            }
+            text = &text[caps[0].len()..];
+        }
+
+        let hashtag = Regex::new(r"(^|\s)#(\w[\w-]*)").unwrap();
+        steering.tags = hashtag
+            .captures_iter(text)
+            .map(|caps| caps[2].to_string())
+            .collect();
+        hashtag.replace_all(text, "").trim().to_string()
+    });
+
+    (steering, subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::OutputProfile;
+
+    fn defaults() -> Vec<SubjectPrefix> {
+        OutputProfile::Jekyll.subject_prefixes()
+    }
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!(
+            Ok(SubjectPrefix {
+                marker: "note".to_string(),
+                action: PrefixAction::FrontMatter("layout".to_string(), "note".to_string()),
+            }),
+            "Note=layout:note".parse()
+        );
+        assert!("draft".parse::<SubjectPrefix>().is_err());
+        assert!("draft=_drafts".parse::<SubjectPrefix>().is_err());
+    }
+
+    #[test]
+    fn test_markers() {
+        let (steering, subject) = parse(&defaults(), Some("[Draft] [note] Lunch".to_string()));
+        assert_eq!(Some("_drafts".to_string()), steering.posts_path);
+        assert_eq!(
+            vec![("layout".to_string(), "note".to_string())],
+            steering.front_matter
+        );
+        assert_eq!(Some("Lunch".to_string()), subject);
+    }
+
+    #[test]
+    fn test_draft_in_front_matter() {
+        let prefixes = OutputProfile::Hugo.subject_prefixes();
+        let (steering, _) = parse(&prefixes, Some("[draft] Lunch".to_string()));
+        assert_eq!(None, steering.posts_path);
+        assert_eq!(
+            vec![("draft".to_string(), "true".to_string())],
+            steering.front_matter
+        );
+    }
+
+    #[test]
+    fn test_unknown_marker_kept() {
+        let (steering, subject) = parse(&defaults(), Some("[Fwd] Lunch".to_string()));
+        assert_eq!(Steering::default(), steering);
+        assert_eq!(Some("[Fwd] Lunch".to_string()), subject);
+    }
+
+    #[test]
+    fn test_hashtags() {
+        let (steering, subject) = parse(&defaults(), Some("Lunch in C# #travel #food".to_string()));
+        assert_eq!(vec!["travel", "food"], steering.tags);
+        assert_eq!(Some("Lunch in C#".to_string()), subject);
+    }
+}
@@ -0,0 +1,136 @@
+use imap::extensions::idle::{stop_on_any, WaitOutcome};
+use imap::types::UnsolicitedResponse;
+use imap::{Connection, Session};
+use log::{debug, info};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::mailbox::Imap;
+use super::mishaps::Mishap;
+use super::settings::Settings;
+use super::source::Source;
+use super::state::StateStore;
+
+// How often we wake up to check for a shutdown request
+const TICK: Duration = Duration::from_secs(1);
+
+// How long an IDLE runs before we re-issue it (and check for a shutdown request).
+// Well under the 10 seconds `docker stop` waits before killing the process.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Keep a session open, publishing new emails as they arrive, until SIGTERM or SIGINT.
+/// Dropped connections are re-established with exponential backoff.
+/// Returns the total number of posts published.
+pub fn run(refresh: &str, settings: &Settings, out_dir: &Path) -> Result<usize, Mishap> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&shutdown))?;
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown))?;
+
+    let mut state = StateStore::open(settings.state_file.as_deref())?;
+    let mut published = 0;
+    let mut backoff = MIN_BACKOFF;
+
+    while !shutdown.load(Ordering::Relaxed) {
+        let mut cycled = false;
+        match watch(
+            refresh,
+            settings,
+            out_dir,
+            &mut state,
+            &shutdown,
+            &mut published,
+            &mut cycled,
+        ) {
+            Ok(()) => break,
+            Err(err) => {
+                super::report("watching mailbox", err);
+                // Only a working session earns a quick retry: an error straight after
+                // connecting (such as a failing commit) would otherwise retry every second
+                if cycled {
+                    backoff = MIN_BACKOFF;
+                }
+                info!("Reconnecting in {:?}", backoff);
+                pause(backoff, &shutdown);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    Ok(published)
+}
+
+/// Process the mailbox, then wait for changes. Returns `Ok` only on a clean shutdown.
+/// `cycled` is set once the mailbox has been processed and waited on without error.
+fn watch(
+    refresh: &str,
+    settings: &Settings,
+    out_dir: &Path,
+    state: &mut StateStore,
+    shutdown: &AtomicBool,
+    published: &mut usize,
+    cycled: &mut bool,
+) -> Result<(), Mishap> {
+    let mut imap = Imap::connect(settings)?;
+
+    let can_idle = imap.session().capabilities()?.has_str("IDLE");
+    debug!("Server supports IDLE: {}", can_idle);
+
+    loop {
+        let outcomes = super::process(refresh, settings, out_dir, state, &mut imap)?;
+        *published += super::published(&outcomes);
+
+        let changed = if can_idle {
+            wait_for_idle(imap.session(), shutdown)?
+        } else {
+            pause(Duration::from_secs(settings.poll_interval), shutdown);
+            true
+        };
+
+        if !changed || shutdown.load(Ordering::Relaxed) {
+            imap.close()?;
+            return Ok(());
+        }
+        *cycled = true;
+    }
+}
+
+/// Wait until the mailbox changes (true) or we are asked to shut down (false).
+fn wait_for_idle(
+    imap_session: &mut Session<Connection>,
+    shutdown: &AtomicBool,
+) -> Result<bool, Mishap> {
+    while !shutdown.load(Ordering::Relaxed) {
+        // New mail may have been announced while we were not idling
+        let announced = imap_session
+            .take_all_unsolicited()
+            .any(|response| matches!(response, UnsolicitedResponse::Exists(_)));
+        if announced {
+            return Ok(true);
+        }
+
+        let outcome = imap_session
+            .idle()
+            .timeout(IDLE_TIMEOUT)
+            .keepalive(false)
+            .wait_while(stop_on_any)?;
+
+        if outcome == WaitOutcome::MailboxChanged {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Sleep for the given duration, but wake early on shutdown.
+fn pause(duration: Duration, shutdown: &AtomicBool) {
+    let until = Instant::now() + duration;
+    while !shutdown.load(Ordering::Relaxed) && Instant::now() < until {
+        thread::sleep(TICK.min(until.saturating_duration_since(Instant::now())));
+    }
+}